@@ -1,6 +1,6 @@
 mod modules;
 
-use clap_v3::App;
+use clap_v3::{App, Arg};
 use std::error::Error;
 
 #[tokio::main]
@@ -12,12 +12,22 @@ async fn main() -> Result<(), Box<dyn Error>> {
             App::new("zui")
                 .about("Show Zero os UI")
                 .version("1.0")
-
+                .arg(
+                    Arg::with_name("broker")
+                        .long("broker")
+                        .help("rbus broker address")
+                        .takes_value(true)
+                        .default_value("redis://0.0.0.0:6379"),
+                ),
         )
         .get_matches();
 
     match matches.subcommand() {
-        ("zui", Some(_sub_m)) => modules::zui::run().await?,
+        ("zui", Some(sub_m)) => {
+            // broker always has a value because of its default
+            let broker = sub_m.value_of("broker").unwrap();
+            modules::zui::run(broker).await?
+        }
         _ => {
             println!("Welcome to zos, please supply subcommand or --help or more info")
         }
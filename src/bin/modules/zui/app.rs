@@ -1,8 +1,8 @@
-use anyhow::Result;
-use rbus::{client::Receiver, Client};
+use anyhow::{anyhow, Result};
+use rbus::Client;
 
 use zos::{
-    bus::api::{self, NetlinkAddresses},
+    bus::api::{self, NetlinkAddresses, NodeInfo, NodeInfoSource},
     {
         app::flags,
         bus::types::{
@@ -15,12 +15,151 @@ use zos::{
 };
 
 use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use super::subscribe::resubscribe;
+
+// how often rarely changing values (node and farm identity) are fetched again
+const IDENTITY_REFRESH: Duration = Duration::from_secs(30);
+// how soon the identity is fetched again after one of its queries failed
+const IDENTITY_RETRY: Duration = Duration::from_secs(5);
+
+// Cadence tracks when a slowly changing value needs to be fetched again
+pub struct Cadence {
+    every: Duration,
+    retry: Duration,
+    next: Option<Instant>,
+}
+
+impl Cadence {
+    pub fn new(every: Duration, retry: Duration) -> Self {
+        Self {
+            every,
+            retry,
+            next: None,
+        }
+    }
+
+    // due returns true if the value was never fetched, or its next fetch
+    // time was reached
+    pub fn due(&self, now: Instant) -> bool {
+        match self.next {
+            None => true,
+            Some(next) => now >= next,
+        }
+    }
+
+    // done marks the value as fetched at now, the next fetch is `every` later
+    pub fn done(&mut self, now: Instant) {
+        self.next = Some(now + self.every);
+    }
+
+    // failed marks the fetch at now as failed, the next fetch is `retry` later
+    pub fn failed(&mut self, now: Instant) {
+        self.next = Some(now + self.retry);
+    }
+}
+
+// keep sets field to value, unless value is an error and field already
+// holds a value that was fetched before
+fn keep<T>(field: &mut Result<T>, value: Result<T>) {
+    if value.is_ok() || field.is_err() {
+        *field = value;
+    }
+}
+
+// Identity holds the node identity values and refreshes them on a cadence
+pub struct Identity {
+    pub info: NodeInfo,
+    cadence: Cadence,
+}
+
+impl Default for Identity {
+    fn default() -> Self {
+        Identity {
+            info: NodeInfo {
+                node_id: Err(anyhow!("loading")),
+                farm_id: Err(anyhow!("loading")),
+                farm_name: Err(anyhow!("loading")),
+                exit_device: Err(anyhow!("loading")),
+            },
+            cadence: Cadence::new(IDENTITY_REFRESH, IDENTITY_RETRY),
+        }
+    }
+}
+
+impl Identity {
+    // tick fetches the identity from source if it is due. Values that were
+    // fetched before are kept if their query fails, and any failure makes
+    // the next fetch happen after the short retry delay
+    pub async fn tick<S: NodeInfoSource + Sync>(&mut self, source: &S, now: Instant) {
+        if !self.cadence.due(now) {
+            return;
+        }
+
+        let info = api::node_info(source).await;
+        let complete = info.is_complete();
+        keep(&mut self.info.node_id, info.node_id);
+        keep(&mut self.info.farm_id, info.farm_id);
+        keep(&mut self.info.farm_name, info.farm_name);
+        keep(&mut self.info.exit_device, info.exit_device);
+
+        if complete {
+            self.cadence.done(now);
+        } else {
+            self.cadence.failed(now);
+        }
+    }
+}
+
+// Stubs answers the node info queries over rbus
+pub struct Stubs {
+    registrar: api::RegistrarStub,
+    identity: api::IdentityManagerStub,
+    network: api::NetworkStub,
+}
+
+impl Stubs {
+    pub fn new(client: &Client) -> Self {
+        Self {
+            registrar: api::RegistrarStub::from(client.clone()),
+            identity: api::IdentityManagerStub::from(client.clone()),
+            network: api::NetworkStub::from(client.clone()),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl NodeInfoSource for Stubs {
+    async fn node_id(&self) -> Result<u32> {
+        self.registrar
+            .node_id()
+            .await
+            .map_err(|err| anyhow!("{}", err))
+    }
+
+    async fn farm_id(&self) -> Result<u32> {
+        self.identity
+            .farm_id()
+            .await
+            .map_err(|err| anyhow!("{}", err))
+    }
+
+    async fn farm_name(&self) -> Result<String> {
+        self.identity.farm().await.map_err(|err| anyhow!("{}", err))
+    }
+
+    async fn exit_device(&self) -> Result<ExitDevice> {
+        self.network
+            .get_public_exit_device()
+            .await
+            .map_err(|err| anyhow!("{}", err))
+    }
+}
+
 pub struct App {
     pub client: Client,
-    pub node_id: Result<u32, rbus::protocol::Error>,
-    pub farm_id: Result<u32, rbus::protocol::Error>,
-    pub exit_device: Result<ExitDevice, rbus::protocol::Error>,
-    pub farm_name: Result<String, rbus::protocol::Error>,
+    pub identity: Identity,
     pub cache_disk: bool,
     pub should_quit: bool,
     pub version: Arc<Mutex<String>>,
@@ -38,9 +177,7 @@ impl App {
     pub fn new(client: Client) -> App {
         App {
             client,
-            node_id: Ok(0),
-            farm_id: Ok(0),
-            farm_name: Ok(String::from("")),
+            identity: Identity::default(),
             cache_disk: false,
             should_quit: false,
             version: Arc::new(Mutex::new(String::from("0.0.0"))),
@@ -57,7 +194,6 @@ impl App {
             dmz_addresses: Arc::new(Mutex::new(String::from("Not Configured"))),
             ygg_addresses: Arc::new(Mutex::new(String::from("Not Configured"))),
             pub_addresses: Arc::new(Mutex::new(String::from("No public config"))),
-            exit_device: Ok(ExitDevice::Unknown),
             running_mode: String::from("unknown"),
         }
     }
@@ -68,296 +204,297 @@ impl App {
         }
     }
     pub async fn poll_version(&self) {
-        let version_monitor = api::VersionMonitorStub::from(self.client.clone());
-        let mut recev: Receiver<Version> = loop {
-            match version_monitor.version().await {
-                Ok(recev) => {
-                    break recev;
-                }
-                Err(err) => {
-                    log::error!("Error executing version method: {}", err);
-                    continue;
-                }
-            };
-        };
-        tokio::spawn({
-            let version_state = Arc::clone(&self.version);
-            async move {
-                loop {
-                    let version = match recev.recv().await {
-                        Some(res) => match res {
-                            Ok(version) => version,
-                            Err(err) => {
-                                log::error!("Error getting version: {}", err);
-                                continue;
-                            }
-                        },
-                        None => continue,
-                    };
-                    *version_state.lock().unwrap() = version.to_string();
-                }
-            }
-        });
+        let client = self.client.clone();
+        let version_state = Arc::clone(&self.version);
+        tokio::spawn(resubscribe(
+            "version",
+            move || {
+                let version_monitor = api::VersionMonitorStub::from(client.clone());
+                async move { version_monitor.version().await }
+            },
+            move |version: Version| *version_state.lock().unwrap() = version.to_string(),
+        ));
     }
     pub async fn poll_memory_usage(&self) {
-        let sys_monitor = api::SystemMonitorStub::from(self.client.clone());
-        let mut recev: Receiver<VirtualMemory> = loop {
-            match sys_monitor.memory().await {
-                Ok(recev) => {
-                    break recev;
-                }
-                Err(err) => {
-                    log::error!("Error executing version method: {}", err);
-                    continue;
-                }
-            };
-        };
-        tokio::spawn({
-            let used_mem_percent = Arc::clone(&self.used_mem_percent);
-            async move {
-                loop {
-                    let mem = match recev.recv().await {
-                        Some(res) => match res {
-                            Ok(mem) => mem,
-                            Err(err) => {
-                                log::error!("Error getting Memory usage: {}", err);
-                                continue;
-                            }
-                        },
-                        None => continue,
-                    };
-                    *used_mem_percent.lock().unwrap() = mem.used_percent;
-                }
-            }
-        });
+        let client = self.client.clone();
+        let used_mem_percent = Arc::clone(&self.used_mem_percent);
+        tokio::spawn(resubscribe(
+            "memory usage",
+            move || {
+                let sys_monitor = api::SystemMonitorStub::from(client.clone());
+                async move { sys_monitor.memory().await }
+            },
+            move |mem: VirtualMemory| *used_mem_percent.lock().unwrap() = mem.used_percent,
+        ));
     }
     pub async fn poll_cpu_usage(&self) {
-        let sys_monitor = api::SystemMonitorStub::from(self.client.clone());
-        let mut recev: Receiver<TimesStat> = loop {
-            match sys_monitor.cpu().await {
-                Ok(recev) => {
-                    break recev;
-                }
-                Err(err) => {
-                    log::error!("Error executing version method: {}", err);
-                    continue;
-                }
-            };
-        };
-        tokio::spawn({
-            let used_cpu_percent = Arc::clone(&self.used_cpu_percent);
-            async move {
-                loop {
-                    let cpu = match recev.recv().await {
-                        Some(res) => match res {
-                            Ok(cpu) => cpu,
-                            Err(err) => {
-                                println!("Error getting CPU usage: {}", err);
-                                continue;
-                            }
-                        },
-                        None => continue,
-                    };
-                    *used_cpu_percent.lock().unwrap() = cpu.percent;
-                }
-            }
-        });
+        let client = self.client.clone();
+        let used_cpu_percent = Arc::clone(&self.used_cpu_percent);
+        tokio::spawn(resubscribe(
+            "cpu usage",
+            move || {
+                let sys_monitor = api::SystemMonitorStub::from(client.clone());
+                async move { sys_monitor.cpu().await }
+            },
+            move |cpu: TimesStat| *used_cpu_percent.lock().unwrap() = cpu.percent,
+        ));
     }
 
     pub async fn poll_reserved_stream(&self) {
-        let statistics = api::StatisticsStub::from(self.client.clone());
-        let mut recev: Receiver<Capacity> = loop {
-            match statistics.reserved().await {
-                Ok(recev) => {
-                    break recev;
-                }
-                Err(err) => {
-                    log::error!("Error getting reserved capacity method: {}", err);
-                    continue;
-                }
-            };
-        };
-        tokio::spawn({
-            let capacity_state = Arc::clone(&self.capacity);
-            async move {
-                loop {
-                    let capacity = match recev.recv().await {
-                        Some(res) => match res {
-                            Ok(version) => version,
-                            Err(err) => {
-                                log::error!("Error getting version: {}", err);
-                                continue;
-                            }
-                        },
-                        None => continue,
-                    };
-                    *capacity_state.lock().unwrap() = capacity;
-                }
-            }
-        });
+        let client = self.client.clone();
+        let capacity_state = Arc::clone(&self.capacity);
+        tokio::spawn(resubscribe(
+            "reserved capacity",
+            move || {
+                let statistics = api::StatisticsStub::from(client.clone());
+                async move { statistics.reserved().await }
+            },
+            move |capacity: Capacity| *capacity_state.lock().unwrap() = capacity,
+        ));
     }
 
     pub async fn poll_zos_addresses(&self) {
-        let network = api::NetworkStub::from(self.client.clone());
-        let mut recev: Receiver<NetlinkAddresses> = loop {
-            match network.zos_addresses().await {
-                Ok(recev) => {
-                    break recev;
-                }
-                Err(err) => {
-                    log::error!("Error executing version method: {}", err);
-                    continue;
-                }
-            };
-        };
-        tokio::spawn({
-            let zos_addresses_state = Arc::clone(&self.zos_addresses);
-            async move {
-                loop {
-                    let zos_addresses = match recev.recv().await {
-                        Some(res) => match res {
-                            Ok(zos_addresses) => zos_addresses,
-                            Err(err) => {
-                                log::error!("Error getting zos addresses: {}", err);
-                                continue;
-                            }
-                        },
-                        None => continue,
-                    };
-                    let mut zos_addresses_str = String::from("");
-                    for address in zos_addresses.iter() {
-                        zos_addresses_str = format!("{} {}", &zos_addresses_str, address)
-                    }
-                    *zos_addresses_state.lock().unwrap() = zos_addresses_str.trim().to_string();
+        let client = self.client.clone();
+        let zos_addresses_state = Arc::clone(&self.zos_addresses);
+        tokio::spawn(resubscribe(
+            "zos addresses",
+            move || {
+                let network = api::NetworkStub::from(client.clone());
+                async move { network.zos_addresses().await }
+            },
+            move |zos_addresses: NetlinkAddresses| {
+                let mut zos_addresses_str = String::from("");
+                // link local addresses are just noise here
+                for address in zos_addresses.iter().filter(|a| !a.is_link_local()) {
+                    zos_addresses_str = format!("{} {}", &zos_addresses_str, address)
                 }
-            }
-        });
+                *zos_addresses_state.lock().unwrap() = zos_addresses_str.trim().to_string();
+            },
+        ));
     }
     pub async fn poll_dmz_addresses(&self) {
-        let network = api::NetworkStub::from(self.client.clone());
-        let mut recev: Receiver<NetlinkAddresses> = loop {
-            match network.dmz_addresses().await {
-                Ok(recev) => {
-                    break recev;
-                }
-                Err(err) => {
-                    log::error!("Error executing version method: {}", err);
-                    continue;
-                }
-            };
-        };
-        tokio::spawn({
-            let dmz_addresses_state = Arc::clone(&self.dmz_addresses);
-            async move {
-                loop {
-                    let dmz_addresses = match recev.recv().await {
-                        Some(res) => match res {
-                            Ok(dmz_addresses) => dmz_addresses,
-                            Err(err) => {
-                                log::error!("Error getting dmz addresses: {}", err);
-                                continue;
-                            }
-                        },
-                        None => continue,
-                    };
-                    let mut dmz_addresses_str = String::from("");
-                    for address in dmz_addresses.iter() {
-                        dmz_addresses_str = format!("{} {}", &dmz_addresses_str, address)
-                    }
-                    *dmz_addresses_state.lock().unwrap() = dmz_addresses_str.trim().to_string();
+        let client = self.client.clone();
+        let dmz_addresses_state = Arc::clone(&self.dmz_addresses);
+        tokio::spawn(resubscribe(
+            "dmz addresses",
+            move || {
+                let network = api::NetworkStub::from(client.clone());
+                async move { network.dmz_addresses().await }
+            },
+            move |dmz_addresses: NetlinkAddresses| {
+                let mut dmz_addresses_str = String::from("");
+                // link local addresses are just noise here
+                for address in dmz_addresses.iter().filter(|a| !a.is_link_local()) {
+                    dmz_addresses_str = format!("{} {}", &dmz_addresses_str, address)
                 }
-            }
-        });
+                *dmz_addresses_state.lock().unwrap() = dmz_addresses_str.trim().to_string();
+            },
+        ));
     }
     pub async fn poll_ygg_addresses(&self) {
-        let network = api::NetworkStub::from(self.client.clone());
-        let mut recev: Receiver<NetlinkAddresses> = loop {
-            match network.ygg_addresses().await {
-                Ok(recev) => {
-                    break recev;
-                }
-                Err(err) => {
-                    log::error!("Error executing version method: {}", err);
-                    continue;
+        let client = self.client.clone();
+        let ygg_addresses_state = Arc::clone(&self.ygg_addresses);
+        tokio::spawn(resubscribe(
+            "ygg addresses",
+            move || {
+                let network = api::NetworkStub::from(client.clone());
+                async move { network.ygg_addresses().await }
+            },
+            move |ygg_addresses: NetlinkAddresses| {
+                let mut ygg_addresses_str = String::from("");
+                // link local addresses are just noise here
+                for address in ygg_addresses.iter().filter(|a| !a.is_link_local()) {
+                    ygg_addresses_str = format!("{} {}", &ygg_addresses_str, address)
                 }
-            };
-        };
-        tokio::spawn({
-            let ygg_addresses_state = Arc::clone(&self.ygg_addresses);
-            async move {
-                loop {
-                    let ygg_addresses = match recev.recv().await {
-                        Some(res) => match res {
-                            Ok(ygg_addresses) => ygg_addresses,
-                            Err(err) => {
-                                log::error!("Error getting ygg addresses: {}", err);
-                                continue;
-                            }
-                        },
-                        None => continue,
-                    };
-                    let mut ygg_addresses_str = String::from("");
-                    for address in ygg_addresses.iter() {
-                        ygg_addresses_str = format!("{} {}", &ygg_addresses_str, address)
-                    }
-                    *ygg_addresses_state.lock().unwrap() = ygg_addresses_str.trim().to_string();
-                }
-            }
-        });
+                *ygg_addresses_state.lock().unwrap() = ygg_addresses_str.trim().to_string();
+            },
+        ));
     }
     pub async fn poll_public_addresses(&self) {
-        let network = api::NetworkStub::from(self.client.clone());
-        let mut recev: Receiver<OptionPublicConfig> = loop {
-            match network.public_addresses().await {
-                Ok(recev) => {
-                    break recev;
-                }
-                Err(err) => {
-                    log::error!("Error executing version method: {}", err);
-                    continue;
-                }
-            };
-        };
-        tokio::spawn({
-            let pub_addresses_state = Arc::clone(&self.pub_addresses);
-            async move {
-                loop {
-                    let pub_addresses = match recev.recv().await {
-                        Some(res) => match res {
-                            Ok(pub_addresses) => pub_addresses,
-                            Err(err) => {
-                                log::error!("Error getting ygg addresses: {}", err);
-                                continue;
-                            }
-                        },
-                        None => continue,
-                    };
-                    let mut addresses = String::from("");
-                    if !pub_addresses.is_set {
-                        *pub_addresses_state.lock().unwrap() = String::from("No public config");
-                    } else {
-                        if let Some(ipv4) = pub_addresses.config.ipv4 {
-                            addresses = format!("{}", ipv4);
-                        }
-                        if let Some(ipv6) = pub_addresses.config.ipv6 {
-                            addresses = format!("{} {}", addresses, ipv6);
-                        }
-                        *pub_addresses_state.lock().unwrap() = addresses;
+        let client = self.client.clone();
+        let pub_addresses_state = Arc::clone(&self.pub_addresses);
+        tokio::spawn(resubscribe(
+            "public addresses",
+            move || {
+                let network = api::NetworkStub::from(client.clone());
+                async move { network.public_addresses().await }
+            },
+            move |pub_addresses: OptionPublicConfig| {
+                let mut addresses = String::from("");
+                if !pub_addresses.is_set {
+                    *pub_addresses_state.lock().unwrap() = String::from("No public config");
+                } else {
+                    if let Some(ipv4) = pub_addresses.config.ipv4 {
+                        addresses = format!("{}", ipv4);
                     }
+                    if let Some(ipv6) = pub_addresses.config.ipv6 {
+                        addresses = format!("{} {}", addresses, ipv6);
+                    }
+                    *pub_addresses_state.lock().unwrap() = addresses;
                 }
-            }
-        });
+            },
+        ));
     }
-    pub async fn on_tick(&mut self) {
+    pub async fn on_tick<S: NodeInfoSource + Sync>(&mut self, source: &S) {
         // Update progress
-        let registrar = api::RegistrarStub::from(self.client.clone());
-        self.node_id = registrar.node_id().await;
-        let identity_manager = api::IdentityManagerStub::from(self.client.clone());
-        self.farm_id = identity_manager.farm_id().await;
-        self.farm_name = identity_manager.farm().await;
-        let network = api::NetworkStub::from(self.client.clone());
-        self.exit_device = network.get_public_exit_device().await;
+        self.identity.tick(source, Instant::now()).await;
         self.cache_disk = flags::check(flags::Flags::LimitedCache);
         self.running_mode = env::RUNTIME.mode.to_string();
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::{Identity, IDENTITY_REFRESH, IDENTITY_RETRY};
+    use anyhow::{bail, Result};
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use std::time::{Duration, Instant};
+    use zos::bus::{api::NodeInfoSource, types::net::ExitDevice};
+
+    // CountingSource counts how many times node info was queried, and fails
+    // the node id or farm name queries when asked to
+    #[derive(Default)]
+    struct CountingSource {
+        calls: AtomicUsize,
+        unregistered: AtomicBool,
+        no_farm: AtomicBool,
+    }
+
+    impl CountingSource {
+        fn calls(&self) -> usize {
+            self.calls.load(Ordering::SeqCst)
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl NodeInfoSource for CountingSource {
+        async fn node_id(&self) -> Result<u32> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            if self.unregistered.load(Ordering::SeqCst) {
+                bail!("node is not registered");
+            }
+            Ok(42)
+        }
+
+        async fn farm_id(&self) -> Result<u32> {
+            Ok(1)
+        }
+
+        async fn farm_name(&self) -> Result<String> {
+            if self.no_farm.load(Ordering::SeqCst) {
+                bail!("farm not found");
+            }
+            Ok("freefarm".into())
+        }
+
+        async fn exit_device(&self) -> Result<ExitDevice> {
+            Ok(ExitDevice::Single)
+        }
+    }
+
+    // ticks drives identity with 250ms ticks from start (excluded) up to
+    // and including end
+    async fn ticks(identity: &mut Identity, source: &CountingSource, start: Instant, end: Instant) {
+        let mut now = start;
+        while now < end {
+            now = std::cmp::min(now + Duration::from_millis(250), end);
+            identity.tick(source, now).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn identity_ticks() {
+        let source = CountingSource::default();
+        let mut identity = Identity::default();
+        let start = Instant::now();
+
+        // first tick fetches everything
+        identity.tick(&source, start).await;
+        assert_eq!(source.calls(), 1);
+        assert!(identity.info.is_complete());
+        assert_eq!(identity.info.node_id.as_ref().unwrap(), &42);
+
+        // fast ticks don't fetch again until the refresh is due
+        ticks(
+            &mut identity,
+            &source,
+            start,
+            start + IDENTITY_REFRESH - Duration::from_millis(250),
+        )
+        .await;
+        assert_eq!(source.calls(), 1);
+
+        // a failing query on refresh keeps the value fetched before, and
+        // retries after the short delay instead of on every tick
+        source.no_farm.store(true, Ordering::SeqCst);
+        let refresh = start + IDENTITY_REFRESH;
+        identity.tick(&source, refresh).await;
+        assert_eq!(source.calls(), 2);
+        assert_eq!(identity.info.farm_name.as_ref().unwrap(), "freefarm");
+
+        ticks(
+            &mut identity,
+            &source,
+            refresh,
+            refresh + IDENTITY_RETRY - Duration::from_millis(250),
+        )
+        .await;
+        assert_eq!(source.calls(), 2);
+
+        let retry = refresh + IDENTITY_RETRY;
+        identity.tick(&source, retry).await;
+        assert_eq!(source.calls(), 3);
+
+        // once all queries succeed, the long refresh applies again
+        source.no_farm.store(false, Ordering::SeqCst);
+        let retry = retry + IDENTITY_RETRY;
+        identity.tick(&source, retry).await;
+        assert_eq!(source.calls(), 4);
+
+        ticks(
+            &mut identity,
+            &source,
+            retry,
+            retry + IDENTITY_REFRESH - Duration::from_millis(250),
+        )
+        .await;
+        assert_eq!(source.calls(), 4);
+    }
+
+    #[tokio::test]
+    async fn identity_ticks_unregistered() {
+        let source = CountingSource::default();
+        source.unregistered.store(true, Ordering::SeqCst);
+        let mut identity = Identity::default();
+        let start = Instant::now();
+
+        // a value that was never fetched shows the error, while the other
+        // values are shown
+        identity.tick(&source, start).await;
+        assert_eq!(source.calls(), 1);
+        assert!(identity.info.node_id.is_err());
+        assert_eq!(identity.info.farm_id.as_ref().unwrap(), &1);
+        assert_eq!(identity.info.farm_name.as_ref().unwrap(), "freefarm");
+        assert!(identity.info.exit_device.is_ok());
+
+        // 20 ticks of 250ms make for one retry
+        ticks(&mut identity, &source, start, start + IDENTITY_RETRY).await;
+        assert_eq!(source.calls(), 2);
+        assert!(identity.info.node_id.is_err());
+
+        source.unregistered.store(false, Ordering::SeqCst);
+        let end = start + IDENTITY_RETRY * 2;
+        ticks(&mut identity, &source, start + IDENTITY_RETRY, end).await;
+        assert_eq!(source.calls(), 3);
+        assert_eq!(identity.info.node_id.as_ref().unwrap(), &42);
+
+        ticks(
+            &mut identity,
+            &source,
+            end,
+            end + IDENTITY_REFRESH - Duration::from_millis(250),
+        )
+        .await;
+        assert_eq!(source.calls(), 3);
+    }
+}
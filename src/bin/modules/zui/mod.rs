@@ -9,14 +9,17 @@ use std::time::{Duration, Instant};
 use tui::backend::{Backend, CrosstermBackend};
 use tui::Terminal;
 
-use app::App;
+use app::{App, Stubs};
 
 mod app;
+mod subscribe;
 mod ui;
 
-pub async fn run() -> Result<(), Box<dyn Error>> {
+pub async fn run(broker: &str) -> Result<(), Box<dyn Error>> {
     // initialize stubs
-    let client = rbus::Client::new("redis://0.0.0.0:6379").await.unwrap();
+    let client = rbus::Client::new(broker)
+        .await
+        .map_err(|err| format!("failed to connect to broker '{}': {}", broker, err))?;
 
     let tick_rate = Duration::from_millis(250);
 
@@ -59,6 +62,7 @@ async fn run_app<B: Backend>(
     mut app: App,
     tick_rate: Duration,
 ) -> io::Result<()> {
+    let stubs = Stubs::new(&app.client);
     let mut last_tick = Instant::now();
     loop {
         terminal.draw(|f| ui::draw(f, &mut app))?;
@@ -74,7 +78,7 @@ async fn run_app<B: Backend>(
             }
         }
         if last_tick.elapsed() >= tick_rate {
-            app.on_tick().await;
+            app.on_tick(&stubs).await;
             last_tick = Instant::now();
         }
         if app.should_quit {
@@ -82,3 +86,13 @@ async fn run_app<B: Backend>(
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::run;
+
+    #[tokio::test]
+    async fn run_bad_broker() {
+        assert!(run("not a broker address").await.is_err());
+    }
+}
@@ -0,0 +1,155 @@
+use async_trait::async_trait;
+use rbus::{client::Receiver, protocol::Error};
+use std::fmt::Display;
+use std::future::Future;
+use std::time::Duration;
+
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+// Subscription is a stream of values received from a stub stream method
+#[async_trait]
+pub trait Subscription<T> {
+    // recv returns the next value, or None once the subscription is closed
+    async fn recv(&mut self) -> Option<Result<T, Error>>;
+}
+
+#[async_trait]
+impl<T> Subscription<T> for Receiver<T>
+where
+    T: Send,
+{
+    async fn recv(&mut self) -> Option<Result<T, Error>> {
+        Receiver::recv(self).await
+    }
+}
+
+// subscribe_with_backoff calls the stub method until it returns a subscription.
+// failed attempts are retried with an exponential backoff (capped at MAX_BACKOFF)
+// so a down broker is not hammered in a busy loop
+pub async fn subscribe_with_backoff<R, E, F, Fut>(name: &str, sub: &F) -> R
+where
+    E: Display,
+    F: Fn() -> Fut,
+    Fut: Future<Output = Result<R, E>>,
+{
+    let mut backoff = MIN_BACKOFF;
+    loop {
+        match sub().await {
+            Ok(recv) => return recv,
+            Err(err) => log::error!("failed to subscribe to {}: {}", name, err),
+        }
+        tokio::time::sleep(backoff).await;
+        backoff = std::cmp::min(backoff * 2, MAX_BACKOFF);
+    }
+}
+
+// resubscribe feeds all values received from the subscription returned by
+// `sub` to `update`. If the subscription is closed (for example when the
+// broker restarts) `sub` is called again so the panel recovers.
+pub async fn resubscribe<T, R, F, Fut, U>(name: &str, sub: F, mut update: U)
+where
+    R: Subscription<T>,
+    F: Fn() -> Fut,
+    Fut: Future<Output = Result<R, Error>>,
+    U: FnMut(T),
+{
+    let mut recv = subscribe_with_backoff(name, &sub).await;
+    loop {
+        match recv.recv().await {
+            Some(Ok(value)) => update(value),
+            Some(Err(err)) => log::error!("error getting {}: {}", name, err),
+            None => {
+                log::warn!("{} subscription closed, subscribing again", name);
+                tokio::time::sleep(MIN_BACKOFF).await;
+                recv = subscribe_with_backoff(name, &sub).await;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{resubscribe, subscribe_with_backoff, Error, Subscription};
+    use async_trait::async_trait;
+    use std::collections::VecDeque;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+    use tokio::sync::mpsc;
+    use tokio::time::Instant;
+
+    // a subscription that yields the given values, then closes
+    struct TestSubscription(VecDeque<u32>);
+
+    #[async_trait]
+    impl Subscription<u32> for TestSubscription {
+        async fn recv(&mut self) -> Option<Result<u32, Error>> {
+            self.0.pop_front().map(Ok)
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn resubscribe_closed() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let (tx, mut rx) = mpsc::unbounded_channel();
+
+        let sub = {
+            let calls = Arc::clone(&calls);
+            move || {
+                let call = calls.fetch_add(1, Ordering::SeqCst) as u32;
+                async move {
+                    Ok(TestSubscription(VecDeque::from(vec![
+                        call * 10,
+                        call * 10 + 1,
+                    ])))
+                }
+            }
+        };
+
+        let handle = tokio::spawn(async move {
+            resubscribe("test", sub, move |value| {
+                let _ = tx.send(value);
+            })
+            .await
+        });
+
+        let mut values = vec![];
+        for _ in 0..4 {
+            values.push(rx.recv().await.unwrap());
+        }
+        handle.abort();
+
+        // first subscription dropped after 2 values, then the loop subscribed again
+        assert_eq!(values, vec![0, 1, 10, 11]);
+        assert!(calls.load(Ordering::SeqCst) >= 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn subscribe_backoff() {
+        let attempts = Arc::new(Mutex::new(vec![]));
+        let sub = {
+            let attempts = Arc::clone(&attempts);
+            move || {
+                let mut attempts = attempts.lock().unwrap();
+                attempts.push(Instant::now());
+                let failed = attempts.len() <= 2;
+                async move {
+                    if failed {
+                        Err("broker is down")
+                    } else {
+                        Ok(())
+                    }
+                }
+            }
+        };
+
+        subscribe_with_backoff("test", &sub).await;
+
+        let attempts = attempts.lock().unwrap();
+        assert_eq!(attempts.len(), 3);
+        // 1s after the first failure, then doubled after the second one
+        assert!(attempts[1] - attempts[0] >= Duration::from_secs(1));
+        assert!(attempts[2] - attempts[1] >= Duration::from_secs(2));
+    }
+}
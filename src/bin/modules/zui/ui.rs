@@ -35,17 +35,17 @@ where
     if app.cache_disk {
         cache_disk = Span::styled("no SSD disks detected", error_style);
     }
-    let node_id_span = match &app.node_id {
+    let node_id_span = match &app.identity.info.node_id {
         Ok(node_id) => Span::styled(format!("{}", node_id), info_style),
-        Err(err) => Span::styled(format!("{}", err), error_style),
+        Err(err) => Span::styled(format!("{:#}", err), error_style),
     };
-    let farm_id_span = match &app.farm_id {
+    let farm_id_span = match &app.identity.info.farm_id {
         Ok(farm_id) => Span::styled(format!("{}", farm_id), info_style),
-        Err(err) => Span::styled(format!("{}", err), error_style),
+        Err(err) => Span::styled(format!("{:#}", err), error_style),
     };
-    let farm_name_span = match &app.farm_name {
+    let farm_name_span = match &app.identity.info.farm_name {
         Ok(farm_name) => Span::styled(farm_name.to_string(), info_style),
-        Err(err) => Span::styled(format!("{}", err), error_style),
+        Err(err) => Span::styled(format!("{:#}", err), error_style),
     };
 
     let text = vec![
@@ -119,9 +119,9 @@ where
         .to_string()
         .trim()
         .to_string();
-    let exit_device = match &app.exit_device {
+    let exit_device = match &app.identity.info.exit_device {
         Ok(exit_device) => format!("{}", exit_device),
-        Err(err) => format!("{}", err),
+        Err(err) => format!("{:#}", err),
     };
     let rows = vec![
         Row::new(vec!["ZOS", &zos]),
@@ -103,3 +103,115 @@ pub trait Flist {
     #[rename("Exists")]
     async fn exists(name: String) -> Result<bool>;
 }
+
+/// NodeInfo is the node identity as shown to the user. Each value
+/// is fetched on its own, so one failing query (say node_id while the
+/// node is not registered yet) does not hide the others
+#[derive(Debug)]
+pub struct NodeInfo {
+    pub node_id: Result<u32>,
+    pub farm_id: Result<FarmID>,
+    pub farm_name: Result<String>,
+    pub exit_device: Result<ExitDevice>,
+}
+
+impl NodeInfo {
+    /// true if all values were fetched successfully
+    pub fn is_complete(&self) -> bool {
+        self.node_id.is_ok()
+            && self.farm_id.is_ok()
+            && self.farm_name.is_ok()
+            && self.exit_device.is_ok()
+    }
+}
+
+/// NodeInfoSource answers the separate queries a NodeInfo is built from.
+/// Usually implemented over the Registrar, IdentityManager and Network stubs
+#[async_trait::async_trait]
+pub trait NodeInfoSource {
+    async fn node_id(&self) -> Result<u32>;
+    async fn farm_id(&self) -> Result<FarmID>;
+    async fn farm_name(&self) -> Result<String>;
+    async fn exit_device(&self) -> Result<ExitDevice>;
+}
+
+/// node_info fetches all node identity values concurrently and returns
+/// them as one NodeInfo. A value that can't be fetched is kept as an error
+/// in its own field
+pub async fn node_info<S: NodeInfoSource + Sync>(source: &S) -> NodeInfo {
+    use anyhow::Context;
+
+    let (node_id, farm_id, farm_name, exit_device) = tokio::join!(
+        async { source.node_id().await.context("failed to get node id") },
+        async { source.farm_id().await.context("failed to get farm id") },
+        async { source.farm_name().await.context("failed to get farm name") },
+        async {
+            source
+                .exit_device()
+                .await
+                .context("failed to get exit device")
+        },
+    );
+
+    NodeInfo {
+        node_id,
+        farm_id,
+        farm_name,
+        exit_device,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{node_info, FarmID, NodeInfoSource};
+    use crate::bus::types::net::ExitDevice;
+    use anyhow::{bail, Result};
+
+    struct TestSource {
+        registered: bool,
+    }
+
+    #[async_trait::async_trait]
+    impl NodeInfoSource for TestSource {
+        async fn node_id(&self) -> Result<u32> {
+            if !self.registered {
+                bail!("node is not registered");
+            }
+            Ok(42)
+        }
+
+        async fn farm_id(&self) -> Result<FarmID> {
+            Ok(1)
+        }
+
+        async fn farm_name(&self) -> Result<String> {
+            Ok("freefarm".into())
+        }
+
+        async fn exit_device(&self) -> Result<ExitDevice> {
+            Ok(ExitDevice::Single)
+        }
+    }
+
+    #[tokio::test]
+    async fn node_info_aggregate() {
+        let info = node_info(&TestSource { registered: true }).await;
+
+        assert!(info.is_complete());
+        assert_eq!(info.node_id.unwrap(), 42);
+        assert_eq!(info.farm_id.unwrap(), 1);
+        assert_eq!(info.farm_name.unwrap(), "freefarm");
+        assert_eq!(info.exit_device.unwrap(), ExitDevice::Single);
+
+        // a failing value does not hide the others
+        let info = node_info(&TestSource { registered: false }).await;
+        assert!(!info.is_complete());
+        assert_eq!(
+            format!("{:#}", info.node_id.unwrap_err()),
+            "failed to get node id: node is not registered"
+        );
+        assert_eq!(info.farm_id.unwrap(), 1);
+        assert_eq!(info.farm_name.unwrap(), "freefarm");
+        assert_eq!(info.exit_device.unwrap(), ExitDevice::Single);
+    }
+}
@@ -13,7 +13,7 @@ use std::{
 /// In the matter of fact, all Ipv4 methods in Go net pkg will always create a 16 bytes
 /// array to hold the Ipv4. Hence the code here need to interpret the format of the IP
 /// not the array length.
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(transparent)]
 pub struct IP(ByteBuf);
 
@@ -29,7 +29,7 @@ impl From<IP> for IpAddr {
             bytes[i] = v;
         }
         let ipv6 = Ipv6Addr::from(bytes);
-        if let Some(ipv4) = ipv6.to_ipv4() {
+        if let Some(ipv4) = ipv6.to_ipv4_mapped() {
             IpAddr::V4(ipv4)
         } else {
             IpAddr::V6(ipv6)
@@ -48,7 +48,7 @@ impl From<&IP> for IpAddr {
             bytes[i] = *v;
         }
         let ipv6 = Ipv6Addr::from(bytes);
-        if let Some(ipv4) = ipv6.to_ipv4() {
+        if let Some(ipv4) = ipv6.to_ipv4_mapped() {
             IpAddr::V4(ipv4)
         } else {
             IpAddr::V6(ipv6)
@@ -56,6 +56,27 @@ impl From<&IP> for IpAddr {
     }
 }
 
+impl From<Ipv4Addr> for IP {
+    fn from(ip: Ipv4Addr) -> Self {
+        Self(ByteBuf::from(ip.octets().to_vec()))
+    }
+}
+
+impl From<Ipv6Addr> for IP {
+    fn from(ip: Ipv6Addr) -> Self {
+        Self(ByteBuf::from(ip.octets().to_vec()))
+    }
+}
+
+impl From<IpAddr> for IP {
+    fn from(ip: IpAddr) -> Self {
+        match ip {
+            IpAddr::V4(ip) => ip.into(),
+            IpAddr::V6(ip) => ip.into(),
+        }
+    }
+}
+
 impl Display for IP {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let addr: IpAddr = self.into();
@@ -63,7 +84,30 @@ impl Display for IP {
     }
 }
 
-#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+/// HardwareAddr is a Golang compatible (net.HardwareAddr) physical (MAC) address
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct HardwareAddr(ByteBuf);
+
+impl From<Vec<u8>> for HardwareAddr {
+    fn from(addr: Vec<u8>) -> Self {
+        Self(ByteBuf::from(addr))
+    }
+}
+
+impl Display for HardwareAddr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (i, v) in self.0.iter().enumerate() {
+            if i > 0 {
+                write!(f, ":")?;
+            }
+            write!(f, "{:02x}", v)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(transparent)]
 pub struct IPMask(ByteBuf);
 
@@ -79,10 +123,58 @@ impl IPMask {
         }
         size
     }
+
+    /// creates a mask of given prefix length. fails if bits is
+    /// longer than an ipv6 address (128)
+    pub fn try_new(bits: u8) -> Result<IPMask, &'static str> {
+        if bits > 128 {
+            return Err("mask prefix length cannot be more than 128");
+        }
+
+        let mut mask: IPMask = bits.into();
+        if bits > 32 {
+            // only an ipv6 mask can be that long, and go only accepts
+            // masks of the full address length (4 or 16 bytes)
+            mask.0.resize(16, 0);
+        }
+
+        Ok(mask)
+    }
+
+    /// returns the prefix length of the mask, None if the mask
+    /// is not in the canonical form (ones followed by zeros)
+    pub fn to_prefix_len(&self) -> Option<u8> {
+        let mut size: u8 = 0;
+        let mut ones = true;
+        for v in self.0.iter() {
+            if !ones {
+                if *v != 0 {
+                    return None;
+                }
+                continue;
+            }
+
+            let leading = v.leading_ones() as u8;
+            if leading < 8 {
+                ones = false;
+                // the rest of the byte must be zeros
+                if v << leading != 0 {
+                    return None;
+                }
+            }
+
+            size += leading;
+        }
+
+        Some(size)
+    }
 }
 
 impl From<u8> for IPMask {
+    /// creates a mask of given prefix length. values above 128
+    /// are clamped to 128, use try_new to reject them instead.
     fn from(size: u8) -> Self {
+        let size = size.min(128);
         // this is probably not the best way
         // to implement
         if size == 0 {
@@ -103,7 +195,7 @@ impl From<u8> for IPMask {
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct IPNet {
     #[serde(rename = "IP")]
     pub ip: IP,
@@ -112,6 +204,95 @@ pub struct IPNet {
     pub mask: IPMask,
 }
 
+impl IPNet {
+    /// creates a new IPNet from ip and prefix length. the prefix is clamped
+    /// to the ip length (32 for ipv4, 128 for ipv6). The mask is always
+    /// the same length as the ip as expected by Go.
+    pub fn new(ip: IpAddr, prefix: u8) -> Self {
+        let len = match ip {
+            IpAddr::V4(_) => 4,
+            IpAddr::V6(_) => 16,
+        };
+
+        let mut mask: IPMask = prefix.min(len as u8 * 8).into();
+        mask.0.resize(len, 0);
+
+        Self {
+            ip: ip.into(),
+            mask,
+        }
+    }
+
+    /// checks if addr is inside this network. addresses of a
+    /// different family are never contained.
+    pub fn contains(&self, addr: &IpAddr) -> bool {
+        match self.network() {
+            Some(net) => net.contains(addr),
+            None => false,
+        }
+    }
+
+    /// checks if the two networks share any address
+    pub fn overlaps(&self, other: &IPNet) -> bool {
+        match (self.network(), other.network()) {
+            (Some(a), Some(b)) => a.contains(&b.network()) || b.contains(&a.network()),
+            _ => false,
+        }
+    }
+
+    /// prefix length of the network
+    pub fn prefix_len(&self) -> u8 {
+        let bits = self.mask.bits();
+        // ipv4 can come with a 16 bytes mask from go
+        if IpAddr::from(&self.ip).is_ipv4() && self.mask.0.len() == 16 {
+            return bits.saturating_sub(96);
+        }
+
+        bits
+    }
+
+    /// checks if the ip is a link local address (169.254.0.0/16 or fe80::/10)
+    pub fn is_link_local(&self) -> bool {
+        match IpAddr::from(&self.ip) {
+            IpAddr::V4(ip) => ip.is_link_local(),
+            IpAddr::V6(ip) => (ip.segments()[0] & 0xffc0) == 0xfe80,
+        }
+    }
+
+    /// checks if the ip is globally reachable. which means it's not
+    /// loopback, link local, private (or unique local for ipv6),
+    /// multicast or unspecified
+    pub fn is_global(&self) -> bool {
+        if self.is_link_local() {
+            return false;
+        }
+
+        match IpAddr::from(&self.ip) {
+            IpAddr::V4(ip) => {
+                !(ip.is_loopback()
+                    || ip.is_private()
+                    || ip.is_multicast()
+                    || ip.is_broadcast()
+                    || ip.is_unspecified())
+            }
+            IpAddr::V6(ip) => {
+                !(ip.is_loopback()
+                    || ip.is_multicast()
+                    || ip.is_unspecified()
+                    || (ip.segments()[0] & 0xfe00) == 0xfc00)
+            }
+        }
+    }
+
+    // network converts to an ipnet::IpNet with the mask applied.
+    // returns None if the prefix is not valid for the ip.
+    fn network(&self) -> Option<ipnet::IpNet> {
+        ipnet::IpNet::new(IpAddr::from(&self.ip), self.prefix_len())
+            .ok()
+            .map(|net| net.trunc())
+    }
+}
+
 impl Display for IPNet {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "{}/{}", self.ip, self.mask.bits())
@@ -136,6 +317,21 @@ struct GoIPNet {
     mask: Option<IPMask>,
 }
 
+impl From<Option<IPNet>> for GoIPNet {
+    fn from(o: Option<IPNet>) -> Self {
+        match o {
+            Some(net) => Self {
+                ip: Some(net.ip),
+                mask: Some(net.mask),
+            },
+            None => Self {
+                ip: None,
+                mask: None,
+            },
+        }
+    }
+}
+
 impl From<GoIPNet> for Option<IPNet> {
     fn from(o: GoIPNet) -> Self {
         match o.ip {
@@ -196,7 +392,7 @@ struct GoPublicConfig {
     domain: Option<String>,
 }
 
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct PublicConfig {
     pub interface_type: InterfaceType,
     pub ipv4: Option<IPNet>,
@@ -223,6 +419,24 @@ impl<'de> Deserialize<'de> for PublicConfig {
     }
 }
 
+impl Serialize for PublicConfig {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        let config = self.clone();
+        GoPublicConfig {
+            typ: config.interface_type,
+            ipv4: config.ipv4.into(),
+            ipv6: config.ipv6.into(),
+            gwv4: config.gwv4,
+            gwv6: config.gwv6,
+            domain: config.domain,
+        }
+        .serialize(serializer)
+    }
+}
+
 /// compatibility struct with go because
 /// we don't have Option in Go we had to
 /// use flags.
@@ -260,15 +474,39 @@ struct GoExitDevice {
     pub as_dual_interface: String,
 }
 
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum ExitDevice {
-    #[serde(rename = "vlan")]
     Single,
-    #[serde(rename = "vlan")]
     Dual(String),
-    #[serde(rename = "")]
     Unknown,
 }
+
+impl Serialize for ExitDevice {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        // Unknown has no encoding, both flags unset is rejected
+        // on decoding
+        let exit = match self {
+            Self::Single => GoExitDevice {
+                is_single: true,
+                is_dual: false,
+                as_dual_interface: String::default(),
+            },
+            Self::Dual(inf) => GoExitDevice {
+                is_single: false,
+                is_dual: true,
+                as_dual_interface: inf.clone(),
+            },
+            Self::Unknown => {
+                return Err(serde::ser::Error::custom("unknown exit interface"));
+            }
+        };
+
+        exit.serialize(serializer)
+    }
+}
 impl<'de> Deserialize<'de> for ExitDevice {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
     where
@@ -299,7 +537,10 @@ impl Display for ExitDevice {
 mod test {
     use serde::de::DeserializeOwned;
 
-    use super::{ExitDevice, IPMask, IPNet, InterfaceType, OptionPublicConfig, PublicConfig, IP};
+    use super::{
+        ExitDevice, HardwareAddr, IPMask, IPNet, InterfaceType, OptionPublicConfig, PublicConfig,
+        IP,
+    };
 
     use std::net::IpAddr;
 
@@ -331,6 +572,37 @@ mod test {
         assert!(mask.0.iter().all(|v| *v == 0xff));
     }
 
+    #[test]
+    fn test_mask_try_new() {
+        for bits in [0, 32, 33, 128] {
+            let mask = IPMask::try_new(bits).unwrap();
+            assert_eq!(mask.bits(), bits);
+            assert_eq!(mask.to_prefix_len(), Some(bits));
+        }
+
+        // 33 is not valid for ipv4 so it's a full length ipv6 mask
+        let mask = IPMask::try_new(33).unwrap();
+        assert_eq!(mask.0.len(), 16);
+
+        assert!(IPMask::try_new(129).is_err());
+
+        // from clamps to 128
+        let mask: IPMask = 200.into();
+        assert_eq!(mask.bits(), 128);
+        assert_eq!(mask.0.len(), 16);
+
+        // non canonical mask
+        let mask = IPMask(serde_bytes::ByteBuf::from(vec![0xff, 0x00, 0xff, 0x00]));
+        assert_eq!(mask.to_prefix_len(), None);
+        let mask = IPMask(serde_bytes::ByteBuf::from(vec![
+            0xff,
+            0b1010_0000,
+            0x00,
+            0x00,
+        ]));
+        assert_eq!(mask.to_prefix_len(), None);
+    }
+
     fn decode<I: AsRef<str>, T: DeserializeOwned>(input: I) -> Result<T, rmp_serde::decode::Error> {
         let data = hex::decode(input.as_ref()).unwrap();
         // hexdump::hexdump(&data);
@@ -367,6 +639,110 @@ mod test {
         assert!(net.to_string() == "2a10:b600:0:be77:f1d6:fc0:40ad:8b29/64");
     }
 
+    #[test]
+    fn test_from_ip_addr() {
+        let addr: IpAddr = "192.168.1.20".parse().unwrap();
+        let ip: IP = addr.into();
+        let data = rmp_serde::to_vec(&ip).unwrap();
+        assert_eq!(hex::encode(&data), "c404c0a80114");
+        let ip: IP = rmp_serde::from_slice(&data).unwrap();
+        assert_eq!(IpAddr::from(ip), addr);
+
+        let addr: IpAddr = "2a10:b600:0:be77:f1d6:fc0:40ad:8b29".parse().unwrap();
+        let ip: IP = addr.into();
+        let data = rmp_serde::to_vec(&ip).unwrap();
+        assert_eq!(hex::encode(&data), "c4102a10b6000000be77f1d60fc040ad8b29");
+        let ip: IP = rmp_serde::from_slice(&data).unwrap();
+        assert_eq!(IpAddr::from(ip), addr);
+
+        // same encoding as the go IPNet 192.168.1.0/24
+        let net = IPNet::new("192.168.1.0".parse().unwrap(), 24);
+        assert_eq!(net.to_string(), "192.168.1.0/24");
+        let data = rmp_serde::to_vec_named(&net).unwrap();
+        assert_eq!(
+            hex::encode(&data),
+            "82a24950c404c0a80100a44d61736bc404ffffff00"
+        );
+
+        // same encoding as the go IPNet 2a10:b600:0:be77::/64
+        let net = IPNet::new("2a10:b600:0:be77::".parse().unwrap(), 64);
+        let data = rmp_serde::to_vec_named(&net).unwrap();
+        assert_eq!(hex::encode(&data), "82a24950c4102a10b6000000be770000000000000000a44d61736bc410ffffffffffffffff0000000000000000");
+        let net: IPNet = rmp_serde::from_slice(&data).unwrap();
+        assert_eq!(net.to_string(), "2a10:b600:0:be77::/64");
+
+        // prefix is clamped to the ip length
+        let net = IPNet::new("10.0.0.1".parse().unwrap(), 64);
+        assert_eq!(net.to_string(), "10.0.0.1/32");
+    }
+
+    #[test]
+    fn test_ipnet_contains() {
+        let net = IPNet::new("192.168.1.0".parse().unwrap(), 24);
+        assert!(net.contains(&"192.168.1.20".parse().unwrap()));
+        assert!(net.contains(&"192.168.1.255".parse().unwrap()));
+        assert!(!net.contains(&"192.168.2.1".parse().unwrap()));
+        assert!(!net.contains(&"::1".parse().unwrap()));
+
+        // go encoded 192.168.1.20/32 with the ip in a 16 bytes array
+        let data = "82a24950c41000000000000000000000ffffc0a80114a44d61736bc404ffffffff";
+        let net: IPNet = decode(data).unwrap();
+        assert!(net.contains(&"192.168.1.20".parse().unwrap()));
+        assert!(!net.contains(&"192.168.1.21".parse().unwrap()));
+
+        let net = IPNet::new("2a10:b600:0:be77::".parse().unwrap(), 64);
+        assert!(net.contains(&"2a10:b600:0:be77:f1d6:fc0:40ad:8b29".parse().unwrap()));
+        assert!(!net.contains(&"2a10:b600:0:be78::1".parse().unwrap()));
+        assert!(!net.contains(&"192.168.1.1".parse().unwrap()));
+    }
+
+    #[test]
+    fn test_ipnet_classify() {
+        // (address, prefix, link local, global)
+        let cases = [
+            ("169.254.10.1", 16, true, false),
+            ("fe80::1c2f:3aff:fe4b:1", 64, true, false),
+            ("10.1.2.3", 8, false, false),
+            ("172.16.0.1", 12, false, false),
+            ("192.168.1.20", 24, false, false),
+            ("127.0.0.1", 8, false, false),
+            ("fd00::1", 8, false, false),
+            ("::1", 128, false, false),
+            ("185.69.166.5", 24, false, true),
+            ("2a10:b600:0:be77:f1d6:fc0:40ad:8b29", 64, false, true),
+            ("302:9e63:7d43:b742:469d:e01a:7c1c:2c8b", 64, false, true),
+        ];
+
+        for (ip, prefix, link_local, global) in cases {
+            let net = IPNet::new(ip.parse().unwrap(), prefix);
+            assert_eq!(net.prefix_len(), prefix, "{}", ip);
+            assert_eq!(net.is_link_local(), link_local, "{}", ip);
+            assert_eq!(net.is_global(), global, "{}", ip);
+        }
+
+        // go encoded 192.168.1.20/32 with the ip in a 16 bytes array
+        let data = "82a24950c41000000000000000000000ffffc0a80114a44d61736bc404ffffffff";
+        let net: IPNet = decode(data).unwrap();
+        assert_eq!(net.prefix_len(), 32);
+        assert!(!net.is_global());
+    }
+
+    #[test]
+    fn test_ipnet_overlaps() {
+        let a = IPNet::new("10.0.0.0".parse().unwrap(), 16);
+        let b = IPNet::new("10.0.5.1".parse().unwrap(), 24);
+        let c = IPNet::new("10.1.0.0".parse().unwrap(), 16);
+        assert!(a.overlaps(&b));
+        assert!(b.overlaps(&a));
+        assert!(a.overlaps(&a));
+        assert!(!a.overlaps(&c));
+        assert!(!b.overlaps(&c));
+
+        let v6 = IPNet::new("2a10:b600::".parse().unwrap(), 32);
+        assert!(!a.overlaps(&v6));
+        assert!(v6.overlaps(&IPNet::new("2a10:b600:0:be77::".parse().unwrap(), 64)));
+    }
+
     #[test]
     fn test_public_config() {
         //config {vlan 192.168.1.20/32 <nil> 192.168.1.1 <nil> }
@@ -397,6 +773,26 @@ mod test {
         assert!(config.is_none());
     }
 
+    #[test]
+    fn test_public_config_encode() {
+        //config {vlan 192.168.1.20/32 <nil> 192.168.1.1 <nil> }
+        let data = "86a454797065a4766c616ea44950763482a24950c41000000000000000000000ffffc0a80114a44d61736bc404ffffffffa44950763682a24950c0a44d61736bc0a3475734c41000000000000000000000ffffc0a80101a3475736c0a6446f6d61696ea0";
+        let config: PublicConfig = decode(data).unwrap();
+
+        // encodes back to the go shape
+        let encoded = rmp_serde::to_vec_named(&config).unwrap();
+        assert_eq!(hex::encode(&encoded), data);
+
+        let decoded: PublicConfig = rmp_serde::from_slice(&encoded).unwrap();
+        assert_eq!(decoded, config);
+
+        //option config {{vlan 192.168.1.20/32 <nil> 192.168.1.1 <nil> } true}
+        let data = "87a454797065a4766c616ea44950763482a24950c41000000000000000000000ffffc0a80114a44d61736bc404ffffffffa44950763682a24950c0a44d61736bc0a3475734c41000000000000000000000ffffc0a80101a3475736c0a6446f6d61696ea0af4861735075626c6963436f6e666967c3";
+        let config: OptionPublicConfig = decode(data).unwrap();
+        let encoded = rmp_serde::to_vec_named(&config).unwrap();
+        assert_eq!(hex::encode(&encoded), data);
+    }
+
     #[test]
     fn test_exit_device() {
         // single {true false }
@@ -415,4 +811,48 @@ mod test {
         let data = "83a8497353696e676c65c2a649734475616cc2af41734475616c496e74657266616365a0";
         assert!(decode::<_, ExitDevice>(data).is_err());
     }
+
+    #[test]
+    fn test_exit_device_encode() {
+        // single {true false }
+        let encoded = rmp_serde::to_vec_named(&ExitDevice::Single).unwrap();
+        assert_eq!(
+            hex::encode(&encoded),
+            "83a8497353696e676c65c3a649734475616cc2af41734475616c496e74657266616365a0"
+        );
+        let exit: ExitDevice = rmp_serde::from_slice(&encoded).unwrap();
+        assert_eq!(exit, ExitDevice::Single);
+
+        // dual (eth0) {false true eth0}
+        let dual = ExitDevice::Dual("eth0".into());
+        let encoded = rmp_serde::to_vec_named(&dual).unwrap();
+        assert_eq!(
+            hex::encode(&encoded),
+            "83a8497353696e676c65c2a649734475616cc3af41734475616c496e74657266616365a465746830"
+        );
+        let exit: ExitDevice = rmp_serde::from_slice(&encoded).unwrap();
+        assert_eq!(exit, dual);
+
+        // unknown can't be decoded, so it's not encoded either
+        assert!(rmp_serde::to_vec_named(&ExitDevice::Unknown).is_err());
+    }
+
+    #[test]
+    fn test_hardware_addr() {
+        // aa:bb:cc:dd:ee:ff
+        let data = "c406aabbccddeeff";
+        let mac: HardwareAddr = decode(data).unwrap();
+        assert_eq!(mac.to_string(), "aa:bb:cc:dd:ee:ff");
+
+        let encoded = rmp_serde::to_vec(&mac).unwrap();
+        assert_eq!(hex::encode(encoded), data);
+
+        // 02:42:0a:00:00:0f
+        let mac: HardwareAddr = vec![0x02, 0x42, 0x0a, 0x00, 0x00, 0x0f].into();
+        assert_eq!(mac.to_string(), "02:42:0a:00:00:0f");
+
+        // nil mac in go
+        let mac: HardwareAddr = decode("c400").unwrap();
+        assert_eq!(mac.to_string(), "");
+    }
 }
@@ -1,7 +1,7 @@
 use crate::Unit;
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Capacity {
     #[serde(rename = "CRU")]
     pub cru: u64,
@@ -15,6 +15,50 @@ pub struct Capacity {
     pub ipv4u: u64,
 }
 
+impl Capacity {
+    /// diff returns what is left of total after this capacity is taken
+    /// out of it. Fields saturate at zero if this capacity is bigger
+    /// than total (over reservation)
+    pub fn diff(&self, total: &Capacity) -> Capacity {
+        Capacity {
+            cru: total.cru.saturating_sub(self.cru),
+            sru: total.sru.saturating_sub(self.sru),
+            hru: total.hru.saturating_sub(self.hru),
+            mru: total.mru.saturating_sub(self.mru),
+            ipv4u: total.ipv4u.saturating_sub(self.ipv4u),
+        }
+    }
+
+    /// percent_of returns this capacity as percentages of total. A field is
+    /// 0 if total has none of this resource, and can be over 100 on over
+    /// reservation
+    pub fn percent_of(&self, total: &Capacity) -> CapacityPercent {
+        let percent = |value: u64, total: u64| {
+            if total == 0 {
+                return 0.0;
+            }
+            value as f64 * 100.0 / total as f64
+        };
+
+        CapacityPercent {
+            cru: percent(self.cru, total.cru),
+            sru: percent(self.sru, total.sru),
+            hru: percent(self.hru, total.hru),
+            mru: percent(self.mru, total.mru),
+            ipv4u: percent(self.ipv4u, total.ipv4u),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CapacityPercent {
+    pub cru: f64,
+    pub sru: f64,
+    pub hru: f64,
+    pub mru: f64,
+    pub ipv4u: f64,
+}
+
 #[derive(Debug, Clone, Copy, Serialize, Deserialize)]
 pub struct VirtualMemory {
     #[serde(rename = "Total")]
@@ -32,3 +76,72 @@ pub struct TimesStat {
     #[serde(rename = "Percent")]
     pub percent: f64,
 }
+
+#[cfg(test)]
+mod test {
+    use super::{Capacity, CapacityPercent};
+
+    const TOTAL: Capacity = Capacity {
+        cru: 8,
+        sru: 1000 * crate::GIGABYTE,
+        hru: 4 * crate::TERABYTE,
+        mru: 32 * crate::GIGABYTE,
+        ipv4u: 0,
+    };
+
+    #[test]
+    fn capacity_diff() {
+        let reserved = Capacity {
+            cru: 2,
+            sru: 250 * crate::GIGABYTE,
+            hru: 0,
+            mru: 8 * crate::GIGABYTE,
+            ipv4u: 0,
+        };
+
+        assert_eq!(
+            reserved.diff(&TOTAL),
+            Capacity {
+                cru: 6,
+                sru: 750 * crate::GIGABYTE,
+                hru: 4 * crate::TERABYTE,
+                mru: 24 * crate::GIGABYTE,
+                ipv4u: 0,
+            }
+        );
+
+        assert_eq!(
+            reserved.percent_of(&TOTAL),
+            CapacityPercent {
+                cru: 25.0,
+                sru: 25.0,
+                hru: 0.0,
+                mru: 25.0,
+                ipv4u: 0.0,
+            }
+        );
+    }
+
+    #[test]
+    fn capacity_over_reserved() {
+        let reserved = Capacity {
+            cru: 16,
+            sru: 1000 * crate::GIGABYTE,
+            hru: 0,
+            mru: 48 * crate::GIGABYTE,
+            ipv4u: 1,
+        };
+
+        let free = reserved.diff(&TOTAL);
+        assert_eq!(free.cru, 0);
+        assert_eq!(free.sru, 0);
+        assert_eq!(free.mru, 0);
+        assert_eq!(free.ipv4u, 0);
+
+        let percent = reserved.percent_of(&TOTAL);
+        assert_eq!(percent.cru, 200.0);
+        assert_eq!(percent.mru, 150.0);
+        // node has no public ips
+        assert_eq!(percent.ipv4u, 0.0);
+    }
+}
@@ -1,13 +1,18 @@
-use crate::system::{Syscalls, System};
+use crate::system::System;
+use crate::versioned;
 use crate::Unit;
 use anyhow::{Context, Result};
 use nix::mount::MsFlags;
-use std::ffi::OsStr;
+use semver::Version;
+use std::ffi::{OsStr, OsString};
 use std::fmt::Display;
+use std::fs::Permissions;
 use std::io::ErrorKind;
 use std::marker::PhantomData;
+use std::os::unix::fs::PermissionsExt;
 use std::path::{Path, PathBuf};
 use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use tokio::fs;
 
 const VOLATILE_ROOT: &str = "/var/run/cache";
@@ -16,8 +21,7 @@ const VOLATILE_ROOT: &str = "/var/run/cache";
 // with
 pub async fn volatile<S: AsRef<str>>(name: S, size: Unit) -> Result<PathBuf> {
     let path = Path::new(VOLATILE_ROOT).join(name.as_ref());
-    let info = crate::storage::mountpoint(&path).await?;
-    if info.is_some() {
+    if crate::storage::is_mounted(&path).await? {
         return Ok(path);
     }
 
@@ -25,7 +29,8 @@ pub async fn volatile<S: AsRef<str>>(name: S, size: Unit) -> Result<PathBuf> {
         .await
         .with_context(|| format!("failed to create directory: {:?}", path))?;
 
-    System.mount(
+    crate::storage::mount::mount(
+        &System,
         Option::<&str>::None,
         &path,
         Some("tmpfs"),
@@ -36,8 +41,15 @@ pub async fn volatile<S: AsRef<str>>(name: S, size: Unit) -> Result<PathBuf> {
     Ok(path)
 }
 
+/// prefix of the first line of an entry that has an expiry time
+const EXPIRES: &str = "@expires=";
+/// name of the file that holds the version of the store entries format
+const VERSION_FILE: &str = ".version";
+
 pub struct Store<T> {
     path: PathBuf,
+    // cache is not enabled during testing (except for the cache own tests)
+    enabled: bool,
     phantom: PhantomData<T>,
 }
 
@@ -49,6 +61,7 @@ impl<T> Store<T> {
 
         Ok(Store {
             path: path,
+            enabled: true,
             phantom: PhantomData::default(),
         })
     }
@@ -60,18 +73,124 @@ impl<T> Store<T> {
         let path = std::env::temp_dir().join(name.as_ref());
         Ok(Store {
             path,
+            enabled: false,
             phantom: PhantomData::default(),
         })
     }
+
+    #[cfg(test)]
+    /// an enabled cache that is stored at given (existing) directory
+    fn at<P: Into<PathBuf>>(path: P) -> Self {
+        Store {
+            path: path.into(),
+            enabled: true,
+            phantom: PhantomData,
+        }
+    }
+}
+
+impl<T> Store<T> {
+    /// versioned makes sure the entries in the store are of the given version
+    /// (format). If the store has no version, or a different one, all entries
+    /// are deleted and the store starts over with the given version.
+    pub async fn versioned(self, version: &Version) -> Result<Self> {
+        if !self.enabled {
+            return Ok(self);
+        }
+
+        let marker = self.path.join(VERSION_FILE);
+        match versioned::peek_version(&marker).await {
+            Ok(current) if &current == version => return Ok(self),
+            Ok(current) => log::info!(
+                "cache {:?} version changed from {} to {}, clearing",
+                self.path,
+                current,
+                version
+            ),
+            Err(versioned::Error::IO(err)) if err.kind() == ErrorKind::NotFound => {
+                log::debug!("cache {:?} has no version, clearing", self.path)
+            }
+            Err(err) => log::warn!("invalid cache {:?} version, clearing: {}", self.path, err),
+        };
+
+        for key in self.keys().await? {
+            self.remove(key).await?;
+        }
+
+        versioned::write_file(&marker, version, &[], Permissions::from_mode(0o644))
+            .await
+            .with_context(|| format!("failed to write cache version: {:?}", marker))?;
+
+        Ok(self)
+    }
+
+    /// list all keys in the cache (including expired entries that
+    /// were not cleaned up yet)
+    pub async fn keys(&self) -> Result<Vec<OsString>> {
+        if !self.enabled {
+            return Ok(Vec::default());
+        }
+
+        let mut keys = vec![];
+        let mut entries = fs::read_dir(&self.path)
+            .await
+            .with_context(|| format!("failed to list cache directory: {:?}", self.path))?;
+
+        while let Some(entry) = entries.next_entry().await? {
+            if entry.file_name() == VERSION_FILE {
+                continue;
+            }
+
+            if entry.file_type().await?.is_file() {
+                keys.push(entry.file_name());
+            }
+        }
+
+        Ok(keys)
+    }
+
+    /// remove key from the cache, removing a missing key is not an error
+    pub async fn remove<S: AsRef<OsStr>>(&self, key: S) -> Result<()> {
+        if !self.enabled {
+            return Ok(());
+        }
+
+        let path = self.path.join(key.as_ref());
+        match fs::remove_file(&path).await {
+            Ok(_) => Ok(()),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(()),
+            Err(err) => Err(err).with_context(|| format!("failed to delete file: {:?}", path)),
+        }
+    }
+}
+
+// milliseconds since epoch
+fn now() -> u128 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis())
+        .unwrap_or_default()
 }
 
 impl<T: Display> Store<T> {
+    /// set key to data, the entry never expires
     pub async fn set<S: AsRef<OsStr>>(&self, key: S, data: &T) -> Result<()> {
-        if cfg!(test) {
+        self.write(key, data.to_string()).await
+    }
+
+    /// set key to data, the entry is considered absent after ttl
+    pub async fn set_ttl<S: AsRef<OsStr>>(&self, key: S, data: &T, ttl: Duration) -> Result<()> {
+        let expires = now() + ttl.as_millis();
+        self.write(key, format!("{}{}\n{}", EXPIRES, expires, data))
+            .await
+    }
+
+    async fn write<S: AsRef<OsStr>>(&self, key: S, content: String) -> Result<()> {
+        if !self.enabled {
             return Ok(());
         }
         let path = self.path.join(key.as_ref());
-        tokio::fs::write(&path, data.to_string())
+        tokio::fs::write(&path, content)
             .await
             .with_context(|| format!("failed to write file: {:?}", path))?;
         Ok(())
@@ -80,8 +199,7 @@ impl<T: Display> Store<T> {
 
 impl<T: FromStr> Store<T> {
     pub async fn get<S: AsRef<OsStr>>(&self, key: S) -> Result<Option<T>> {
-        // cache is not enabled during testing.
-        if cfg!(test) {
+        if !self.enabled {
             return Ok(None);
         }
         let path = self.path.join(key.as_ref());
@@ -93,6 +211,29 @@ impl<T: FromStr> Store<T> {
 
         let st = String::from_utf8(data).context("invalid file content not valid utf8")?;
 
+        let st = match st.strip_prefix(EXPIRES) {
+            None => st.as_str(),
+            Some(entry) => {
+                let (expires, value) = entry
+                    .split_once('\n')
+                    .with_context(|| format!("invalid cache entry: {:?}", path))?;
+                let expires: u128 = expires
+                    .parse()
+                    .with_context(|| format!("invalid cache entry expiry: {:?}", path))?;
+
+                if expires <= now() {
+                    // lazy clean up of expired entries
+                    match tokio::fs::remove_file(&path).await {
+                        Ok(_) => (),
+                        Err(err) if err.kind() == ErrorKind::NotFound => (),
+                        Err(err) => anyhow::bail!(err),
+                    };
+                    return Ok(None);
+                }
+                value
+            }
+        };
+
         let t: T = match st.parse() {
             Ok(t) => t,
             Err(_) => anyhow::bail!("failed to file content: {:?}", path),
@@ -102,4 +243,103 @@ impl<T: FromStr> Store<T> {
     }
 }
 
-// todo! add tests for cache
+#[cfg(test)]
+mod test {
+    use super::Store;
+    use semver::Version;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn store_set_get() {
+        let dir = tempfile::tempdir().unwrap();
+        let store: Store<u64> = Store::at(dir.path());
+
+        assert!(store.get("key").await.unwrap().is_none());
+
+        store.set("key", &10).await.unwrap();
+        assert_eq!(store.get("key").await.unwrap(), Some(10));
+    }
+
+    #[tokio::test]
+    async fn store_ttl() {
+        let dir = tempfile::tempdir().unwrap();
+        let store: Store<u64> = Store::at(dir.path());
+
+        store
+            .set_ttl("short", &10, Duration::from_millis(50))
+            .await
+            .unwrap();
+        store
+            .set_ttl("long", &20, Duration::from_secs(3600))
+            .await
+            .unwrap();
+
+        assert_eq!(store.get("short").await.unwrap(), Some(10));
+
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        assert_eq!(store.get("short").await.unwrap(), None);
+        assert_eq!(store.get("long").await.unwrap(), Some(20));
+        // expired entry is deleted
+        assert!(!dir.path().join("short").exists());
+    }
+
+    #[tokio::test]
+    async fn store_keys_remove() {
+        let dir = tempfile::tempdir().unwrap();
+        let store: Store<u64> = Store::at(dir.path());
+
+        store.set("sda", &1).await.unwrap();
+        store.set("sdb", &2).await.unwrap();
+        store
+            .set_ttl("sdc", &3, Duration::from_secs(3600))
+            .await
+            .unwrap();
+
+        let mut keys = store.keys().await.unwrap();
+        keys.sort();
+        assert_eq!(keys, vec!["sda", "sdb", "sdc"]);
+
+        store.remove("sdb").await.unwrap();
+        // removing a missing key is fine
+        store.remove("sdb").await.unwrap();
+
+        let mut keys = store.keys().await.unwrap();
+        keys.sort();
+        assert_eq!(keys, vec!["sda", "sdc"]);
+        assert_eq!(store.get("sdb").await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn store_versioned() {
+        let dir = tempfile::tempdir().unwrap();
+        let v1 = Version::new(1, 0, 0);
+        let v2 = Version::new(2, 0, 0);
+
+        // entry written by an old (unversioned) store
+        tokio::fs::write(dir.path().join("sda"), "garbage")
+            .await
+            .unwrap();
+
+        let store: Store<u64> = Store::at(dir.path()).versioned(&v1).await.unwrap();
+        // old entry is gone instead of failing to parse
+        assert_eq!(store.get("sda").await.unwrap(), None);
+        assert!(store.keys().await.unwrap().is_empty());
+
+        store.set("sda", &1).await.unwrap();
+
+        // same version keeps the entries
+        let store: Store<u64> = Store::at(dir.path()).versioned(&v1).await.unwrap();
+        assert_eq!(store.get("sda").await.unwrap(), Some(1));
+
+        // a new version rebuilds the store
+        let store: Store<u64> = Store::at(dir.path()).versioned(&v2).await.unwrap();
+        assert_eq!(store.get("sda").await.unwrap(), None);
+        assert_eq!(
+            crate::versioned::peek_version(dir.path().join(".version"))
+                .await
+                .unwrap(),
+            v2
+        );
+    }
+}
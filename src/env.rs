@@ -168,4 +168,26 @@ mod test {
         );
         assert_eq!(RUNTIME.substrate_url.len(), 4);
     }
+
+    #[test]
+    fn env_from_params() {
+        use super::from_params;
+        use crate::kernel::Params;
+
+        let env = from_params(Params::from("runmode=dev farmer_id=11 nomodeset")).unwrap();
+        assert_eq!(env.mode, RunMode::Dev);
+        assert_eq!(env.mode.to_string(), "development");
+        assert_eq!(env.farmer_id, Some(11));
+        assert_eq!(env.bin_repo, "tf-zos-v3-bins.dev");
+
+        let env = from_params(Params::from("runmode=test")).unwrap();
+        assert_eq!(env.mode, RunMode::Test);
+        assert_eq!(env.substrate_url, vec!["wss://tfchain.test.grid.tf/"]);
+
+        // default to main if runmode is not set
+        let env = from_params(Params::from("nomodeset")).unwrap();
+        assert_eq!(env.mode, RunMode::Main);
+
+        assert!(from_params(Params::from("runmode=unknown")).is_err());
+    }
 }
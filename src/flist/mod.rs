@@ -3,6 +3,28 @@ use crate::bus::api::Flist;
 use crate::bus::types::storage::MountOptions;
 use anyhow::Result;
 use std::path::Path;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("mount '{name}' not found")]
+    NotMounted { name: String },
+
+    #[error("mount '{name}' already exists")]
+    AlreadyMounted { name: String },
+
+    #[error("invalid mount name '{name}'")]
+    InvalidName { name: String },
+
+    #[error("failed to download flist '{url}': {reason}")]
+    Download { url: String, reason: String },
+
+    #[error("system error: {0}")]
+    System(#[from] crate::system::Error),
+
+    #[error("io error: {0}")]
+    IO(#[from] std::io::Error),
+}
 
 pub struct FListDaemon;
 
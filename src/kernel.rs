@@ -1,3 +1,4 @@
+use anyhow::{Context, Result};
 use std::collections::hash_map::Entry;
 use std::collections::HashMap;
 
@@ -30,6 +31,13 @@ impl Params {
     }
 }
 
+impl From<&str> for Params {
+    // parse params from a cmdline formatted string
+    fn from(cmdline: &str) -> Self {
+        parse_params(cmdline.into())
+    }
+}
+
 fn parse_params(content: String) -> Params {
     let mut params_map = HashMap::new();
     if let Some(cmdline) = shlex::split(&content) {
@@ -62,17 +70,25 @@ fn parse_params(content: String) -> Params {
     Params(params_map)
 }
 
-//params Get kernel cmdline arguments
+const CMDLINE: &str = "/proc/cmdline";
+
+// params reads and parses kernel cmdline arguments
+pub fn params() -> Result<Params> {
+    let content =
+        fs::read_to_string(CMDLINE).with_context(|| format!("failed to read {}", CMDLINE))?;
+
+    Ok(parse_params(content))
+}
+
+//get kernel cmdline arguments, errors are logged and empty params are returned
 pub fn get() -> Params {
-    let content = match fs::read_to_string("/proc/cmdline") {
-        Ok(content) => content,
+    match params() {
+        Ok(params) => params,
         Err(err) => {
-            log::error!("failed to get cmdline: {}", err);
-            return Params(HashMap::default());
+            log::error!("failed to get cmdline: {:#}", err);
+            Params(HashMap::default())
         }
-    };
-
-    parse_params(content)
+    }
 }
 
 #[cfg(test)]
@@ -104,4 +120,23 @@ mod test {
         assert_eq!(params.value("farmer_id"), Some("11"));
         assert_eq!(params.value("with_spaces"), Some("with spaces"))
     }
+
+    #[test]
+    fn test_parse_flags() {
+        let params = parse_params("runmode=test nomodeset zos-debug=\"\" farmer_id=1".into());
+
+        assert_eq!(params.value("runmode"), Some("test"));
+        assert!(params.exists("nomodeset"));
+        assert_eq!(params.value("nomodeset"), None);
+        // a key with empty value is not a flag
+        assert_eq!(params.value("zos-debug"), Some(""));
+        assert!(!params.exists("missing"));
+        assert_eq!(params.value("missing"), None);
+    }
+
+    #[test]
+    fn test_params() {
+        // /proc/cmdline always exists on linux
+        assert!(super::params().is_ok());
+    }
 }
@@ -8,6 +8,8 @@ pub mod storage;
 pub mod system;
 pub mod versioned;
 
+use anyhow::{bail, Context, Result};
+
 // Unit is a size measuring unit 1 unit = 1 byte
 pub type Unit = u64;
 
@@ -16,6 +18,85 @@ pub const MEGABYTE: Unit = 1024 * KILOBYTE;
 pub const GIGABYTE: Unit = 1024 * MEGABYTE;
 pub const TERABYTE: Unit = 1024 * GIGABYTE;
 
+// Size wraps a Unit to display it in human readable form
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Size(pub Unit);
+
+impl std::fmt::Display for Size {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        const UNITS: [(Unit, &str); 4] = [
+            (TERABYTE, "TiB"),
+            (GIGABYTE, "GiB"),
+            (MEGABYTE, "MiB"),
+            (KILOBYTE, "KiB"),
+        ];
+
+        // use the largest unit that gives a value >= 1, moving one unit up
+        // if rounding the value would show it as 1024.00
+        for (index, (unit, name)) in UNITS.iter().enumerate() {
+            if self.0 < *unit {
+                continue;
+            }
+
+            let value = self.0 as f64 / *unit as f64;
+            if index > 0 && (value * 100.0).round() / 100.0 >= 1024.0 {
+                let (unit, name) = UNITS[index - 1];
+                return write!(f, "{:.2} {}", self.0 as f64 / unit as f64, name);
+            }
+
+            return write!(f, "{:.2} {}", value, name);
+        }
+
+        write!(f, "{} B", self.0)
+    }
+}
+
+// parse_size parses a human readable size (50GB, 512MiB, 1T, 1024) into a Unit.
+// suffixes are case insensitive and always binary (1K = 1024), a bare number is
+// in bytes.
+pub fn parse_size<S: AsRef<str>>(input: S) -> Result<Unit> {
+    let input = input.as_ref().trim();
+    let at = input
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(input.len());
+    let (number, suffix) = input.split_at(at);
+
+    let unit = match suffix.trim().to_lowercase().as_str() {
+        "" | "b" => 1,
+        "k" | "kb" | "ki" | "kib" => KILOBYTE,
+        "m" | "mb" | "mi" | "mib" => MEGABYTE,
+        "g" | "gb" | "gi" | "gib" => GIGABYTE,
+        "t" | "tb" | "ti" | "tib" => TERABYTE,
+        _ => bail!("invalid size '{}': unknown unit '{}'", input, suffix),
+    };
+
+    let size = if number.contains('.') {
+        let number: f64 = number
+            .parse()
+            .with_context(|| format!("invalid size '{}'", input))?;
+        let size = number * unit as f64;
+        if size >= Unit::MAX as f64 {
+            bail!("invalid size '{}': too large", input);
+        }
+        size as Unit
+    } else {
+        let number: Unit = number
+            .parse()
+            .with_context(|| format!("invalid size '{}'", input))?;
+        match number.checked_mul(unit) {
+            Some(size) => size,
+            None => bail!("invalid size '{}': too large", input),
+        }
+    };
+
+    Ok(size)
+}
+
+// format_size renders size in human readable form (1073741824 => 1.00 GiB)
+pub fn format_size(size: Unit) -> String {
+    Size(size).to_string()
+}
+
 #[cfg(test)]
 mod test {
     #[test]
@@ -25,4 +106,42 @@ mod test {
         assert_eq!(30 * super::GIGABYTE, 30 * 1024 * 1024 * 1024);
         assert_eq!(40 * super::TERABYTE, 40 * 1024 * 1024 * 1024 * 1024);
     }
+
+    #[test]
+    fn test_format_size() {
+        use super::*;
+
+        assert_eq!(format_size(0), "0 B");
+        assert_eq!(format_size(1023), "1023 B");
+        assert_eq!(format_size(KILOBYTE), "1.00 KiB");
+        assert_eq!(format_size(1536), "1.50 KiB");
+        assert_eq!(format_size(MEGABYTE - 1), "1.00 MiB");
+        assert_eq!(format_size(MEGABYTE), "1.00 MiB");
+        assert_eq!(format_size(GIGABYTE - 1), "1.00 GiB");
+        assert_eq!(format_size(1073741824), "1.00 GiB");
+        assert_eq!(format_size(GIGABYTE + 256 * MEGABYTE), "1.25 GiB");
+        assert_eq!(format_size(TERABYTE), "1.00 TiB");
+        assert_eq!(format_size(2048 * TERABYTE), "2048.00 TiB");
+        assert_eq!(Size(10 * MEGABYTE).to_string(), "10.00 MiB");
+    }
+
+    #[test]
+    fn test_parse_size() {
+        use super::*;
+
+        assert_eq!(parse_size("50GB").unwrap(), 50 * GIGABYTE);
+        assert_eq!(parse_size("512MiB").unwrap(), 512 * MEGABYTE);
+        assert_eq!(parse_size("1T").unwrap(), TERABYTE);
+        assert_eq!(parse_size("1024").unwrap(), 1024);
+        assert_eq!(parse_size("10 kb").unwrap(), 10 * KILOBYTE);
+        assert_eq!(parse_size("1.5g").unwrap(), GIGABYTE + 512 * MEGABYTE);
+        assert_eq!(parse_size("100B").unwrap(), 100);
+
+        assert!(parse_size("abc").is_err());
+        assert!(parse_size("").is_err());
+        assert!(parse_size("-1G").is_err());
+        assert!(parse_size("10X").is_err());
+        assert!(parse_size("1.2.3M").is_err());
+        assert!(parse_size("20000000T").is_err());
+    }
 }
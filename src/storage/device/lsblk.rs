@@ -1,11 +1,14 @@
-use super::{Device, DeviceManager, DeviceType, Filesystem};
+use super::{Device, DeviceManager, DeviceType, Filesystem, SeekResult};
 use crate::system::{Command, Executor};
 use crate::Unit;
 use anyhow::{Context, Result};
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Duration;
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct LsblkDevice {
     // hold data here
     path: PathBuf,
@@ -15,6 +18,24 @@ pub struct LsblkDevice {
     filesystem: Option<String>,
     label: Option<String>,
     rota: bool,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    children: Vec<LsblkDevice>,
+}
+
+impl LsblkDevice {
+    /// partitions of this device as reported by lsblk, empty if the
+    /// device is not partitioned
+    pub fn partitions(&self) -> &[LsblkDevice] {
+        &self.children
+    }
+
+    fn flatten(mut self, into: &mut Vec<LsblkDevice>) {
+        let children = std::mem::take(&mut self.children);
+        into.push(self);
+        for child in children {
+            child.flatten(into);
+        }
+    }
 }
 
 impl Device for LsblkDevice {
@@ -53,6 +74,9 @@ struct Devices {
 struct SeekOutput {
     #[serde(rename = "type")]
     typ: DeviceType,
+    // average seek time in microseconds
+    #[serde(default)]
+    elapsed: u64,
 }
 
 #[derive(Debug)]
@@ -61,6 +85,8 @@ where
     E: Executor,
 {
     exec: E,
+    // label to device cache, if enabled
+    labels: Option<Mutex<HashMap<String, LsblkDevice>>>,
 }
 
 impl<E> LsBlk<E>
@@ -68,8 +94,32 @@ where
     E: Executor,
 {
     #[cfg(test)]
-    fn new(exec: E) -> Self {
-        LsBlk { exec }
+    pub(crate) fn new(exec: E) -> Self {
+        LsBlk { exec, labels: None }
+    }
+
+    /// enable caching of labeled device lookups. the cache is
+    /// invalidated when a device is formatted, or by an explicit
+    /// call to invalidate
+    pub fn with_label_cache(mut self) -> Self {
+        self.labels = Some(Mutex::default());
+        self
+    }
+}
+
+impl<E> LsBlk<E>
+where
+    E: Executor + Send + Sync,
+{
+    /// same as devices() but also returns all partitions (and their children)
+    /// as separate entries right after their parent device
+    pub async fn devices_flat(&self) -> Result<Vec<LsblkDevice>> {
+        let mut flat = vec![];
+        for device in self.devices().await? {
+            device.flatten(&mut flat);
+        }
+
+        Ok(flat)
     }
 }
 
@@ -77,7 +127,9 @@ impl Default for LsBlk<crate::system::System> {
     fn default() -> Self {
         LsBlk {
             exec: crate::system::System,
+            labels: None,
         }
+        .with_label_cache()
     }
 }
 
@@ -130,16 +182,28 @@ where
 
     async fn labeled<S: AsRef<str> + Send>(&self, label: S) -> Result<Self::Device> {
         let label = label.as_ref();
+        if let Some(labels) = &self.labels {
+            if let Some(device) = labels.lock().unwrap().get(label) {
+                return Ok(device.clone());
+            }
+        }
+
         let devices = self.devices().await?;
+        let mut found = None;
         for device in devices {
             if let Some(lb) = device.label() {
-                if lb == label {
-                    return Ok(device);
+                // cache all labeled devices from the listing
+                if let Some(labels) = &self.labels {
+                    labels.lock().unwrap().insert(lb.into(), device.clone());
+                }
+
+                if lb == label && found.is_none() {
+                    found = Some(device);
                 }
             }
         }
 
-        anyhow::bail!("device not found");
+        found.ok_or_else(|| anyhow::anyhow!("device not found"))
     }
 
     async fn shutdown(&self, device: &Self::Device) -> Result<()> {
@@ -153,6 +217,10 @@ where
     }
 
     async fn seektime(&self, device: &Self::Device) -> Result<DeviceType> {
+        Ok(self.seektime_detailed(device).await?.device_type)
+    }
+
+    async fn seektime_detailed(&self, device: &Self::Device) -> Result<SeekResult> {
         let cmd = Command::new("seektime").arg("-j").arg(device.path());
 
         let output =
@@ -163,44 +231,69 @@ where
         let output: SeekOutput =
             serde_json::from_slice(&output).context("failed to decode seektime output")?;
 
-        Ok(output.typ)
+        Ok(SeekResult {
+            device_type: output.typ,
+            avg_latency: Duration::from_micros(output.elapsed),
+        })
     }
 
     async fn format(
         &self,
         device: Self::Device,
-        _filesystem: Filesystem,
+        filesystem: Filesystem,
         force: bool,
     ) -> Result<Self::Device> {
-        // only btrfs is supported atm
-        //let cmd
         let id = uuid::Uuid::new_v4();
-        let mut cmd = Command::new("mkfs.btrfs")
-            .arg("-L")
-            .arg(id.hyphenated().to_string());
-        if force {
-            cmd = cmd.arg("-f");
-        }
+        let cmd = match filesystem {
+            Filesystem::Btrfs => {
+                let mut cmd = Command::new("mkfs.btrfs");
+                if force {
+                    cmd = cmd.arg("-f");
+                }
+
+                cmd.arg("-L").arg(id.hyphenated().to_string())
+            }
+            Filesystem::Ext4 => {
+                let mut cmd = Command::new("mkfs.ext4");
+                if force {
+                    cmd = cmd.arg("-F");
+                }
+
+                // ext4 labels are limited to 16 bytes
+                let mut label = id.simple().to_string();
+                label.truncate(16);
+                cmd.arg("-L").arg(label)
+            }
+        };
 
-        cmd = cmd.arg(device.path());
+        let cmd = cmd.arg(device.path());
 
         self.exec
             .run(&cmd)
             .await
-            .with_context(|| format!("failed to run seektime for device: {:?}", device.path()))?;
+            .with_context(|| format!("failed to format device: {:?}", device.path()))?;
 
+        // labels changed
+        self.invalidate();
         self.device(device.path()).await
     }
+
+    fn invalidate(&self) {
+        if let Some(labels) = &self.labels {
+            labels.lock().unwrap().clear();
+        }
+    }
 }
 
 #[cfg(test)]
 mod test {
-    use super::{DeviceManager, LsBlk};
+    use super::{DeviceManager, LsBlk, LsblkDevice};
     use crate::{
-        storage::device::{Device, DeviceType},
+        storage::device::{Device, DeviceType, Filesystem, SeekResult},
         system::Command,
     };
     use std::path::Path;
+    use std::time::Duration;
 
     const LSBLK_LIST_VALID: &str = r#"{
         "blockdevices": [
@@ -218,6 +311,23 @@ mod test {
         ]
      }"#;
 
+    const LSBLK_LIST_PARTITIONED: &str = r#"{
+        "blockdevices": [
+           {"path":"/dev/sda", "name":"/dev/sda", "size":512110190592, "subsystems":"block:scsi:pci", "fstype":null, "label":null, "rota":false,
+              "children": [
+                 {"path":"/dev/sda1", "name":"/dev/sda1", "size":536870912, "subsystems":"block:scsi:pci", "fstype":"vfat", "label":"EFI", "rota":false},
+                 {"path":"/dev/sda2", "name":"/dev/sda2", "size":511573319680, "subsystems":"block:scsi:pci", "fstype":"btrfs", "label":"aa8a31a4-cbe8-4615-a6fe-155a9418cd0a", "rota":false}
+              ]
+           },
+           {"path":"/dev/sdb", "name":"/dev/sdb", "size":3000592982016, "subsystems":"block:scsi:pci", "fstype":"btrfs", "label":"5ecdbb3c-b687-4048-b505-7a6756c2de76", "rota":true},
+           {"path":"/dev/sdx", "name":"/dev/sdx", "size":12341245, "subsystems":"block:scsi:usb:pci", "fstype": null, "label": null, "rota":false,
+              "children": [
+                 {"path":"/dev/sdx1", "name":"/dev/sdx1", "size":12341245, "subsystems":"block:scsi:usb:pci", "fstype":"vfat", "label":"ZOS", "rota":false}
+              ]
+           }
+        ]
+     }"#;
+
     #[test]
     fn default() {
         // makes sure default implementation works
@@ -258,6 +368,49 @@ mod test {
         assert!(matches!(devices[3].label(), None));
     }
 
+    #[tokio::test]
+    async fn lsblk_devices_partitioned() {
+        let mut exec = crate::system::MockExecutor::default();
+        let cmd = Command::new("lsblk")
+            .arg("--json")
+            .arg("-o")
+            .arg("PATH,NAME,SIZE,SUBSYSTEMS,FSTYPE,LABEL,ROTA")
+            .arg("--bytes")
+            .arg("--exclude")
+            .arg("1,2,11");
+
+        exec.expect_run()
+            .withf(move |arg: &Command| arg == &cmd)
+            .times(2)
+            .returning(|_: &Command| Ok(Vec::from(LSBLK_LIST_PARTITIONED)));
+
+        //mut is only needed for the checkpoint
+        let mut lsblk = LsBlk::new(exec);
+
+        let devices = lsblk.devices().await.expect("failed to get devices");
+
+        assert!(devices.len() == 2);
+        let partitions = devices[0].partitions();
+        assert!(partitions.len() == 2);
+        assert!(partitions[0].path() == Path::new("/dev/sda1"));
+        assert!(matches!(partitions[1].filesystem(), Some(f) if f == "btrfs"));
+        assert!(devices[1].partitions().is_empty());
+
+        let flat = lsblk.devices_flat().await.expect("failed to get devices");
+        lsblk.exec.checkpoint();
+
+        let paths: Vec<&Path> = flat.iter().map(|d| d.path()).collect();
+        assert_eq!(
+            paths,
+            vec![
+                Path::new("/dev/sda"),
+                Path::new("/dev/sda1"),
+                Path::new("/dev/sda2"),
+                Path::new("/dev/sdb"),
+            ]
+        );
+    }
+
     #[tokio::test]
     async fn lsblk_device() {
         let mut exec = crate::system::MockExecutor::default();
@@ -438,8 +591,243 @@ mod test {
     }
 
     #[tokio::test]
-    #[ignore]
+    async fn lsblk_seektime_detailed() {
+        let mut exec = crate::system::MockExecutor::default();
+        let cmd = Command::new("seektime").arg("-j").arg("/dev/sdb");
+
+        exec.expect_run()
+            .withf(move |arg: &Command| arg == &cmd)
+            .times(1)
+            .returning(|_: &Command| {
+                Ok(Vec::from(
+                    r#"{"disk": "/dev/sdb", "type": "HDD", "elapsed": 12500}"#,
+                ))
+            });
+
+        let lsblk = LsBlk::new(exec);
+        let device = LsblkDevice {
+            path: "/dev/sdb".into(),
+            size: 1 * crate::TERABYTE,
+            subsystems: "block:scsi:pci".into(),
+            filesystem: None,
+            label: None,
+            rota: true,
+            children: vec![],
+        };
+
+        let result = lsblk.seektime_detailed(&device).await.unwrap();
+        assert_eq!(
+            result,
+            SeekResult {
+                device_type: DeviceType::HDD,
+                avg_latency: Duration::from_micros(12500),
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn lsblk_labeled_cache() {
+        let list_cmd = Command::new("lsblk")
+            .arg("--json")
+            .arg("-o")
+            .arg("PATH,NAME,SIZE,SUBSYSTEMS,FSTYPE,LABEL,ROTA")
+            .arg("--bytes")
+            .arg("--exclude")
+            .arg("1,2,11");
+
+        let mut exec = crate::system::MockExecutor::default();
+        let cmd = list_cmd.clone();
+        exec.expect_run()
+            .withf(move |arg: &Command| arg == &cmd)
+            .times(1)
+            .returning(|_: &Command| Ok(Vec::from(LSBLK_LIST_VALID)));
+
+        let mut lsblk = LsBlk::new(exec).with_label_cache();
+
+        // lsblk runs only once for all labeled devices
+        let device = lsblk
+            .labeled("5ecdbb3c-b687-4048-b505-7a6756c2de76")
+            .await
+            .unwrap();
+        assert_eq!(device.path(), Path::new("/dev/sdb"));
+        let device = lsblk
+            .labeled("5ecdbb3c-b687-4048-b505-7a6756c2de76")
+            .await
+            .unwrap();
+        assert_eq!(device.path(), Path::new("/dev/sdb"));
+        let device = lsblk
+            .labeled("aa8a31a4-cbe8-4615-a6fe-155a9418cd0a")
+            .await
+            .unwrap();
+        assert_eq!(device.path(), Path::new("/dev/sda"));
+        lsblk.exec.checkpoint();
+
+        // format invalidates the cache
+        lsblk
+            .exec
+            .expect_run()
+            .withf(|arg: &Command| arg.to_string().starts_with(r#""mkfs.btrfs""#))
+            .times(1)
+            .returning(|_: &Command| Ok(Vec::default()));
+
+        let device_cmd = list_cmd.clone().arg("/dev/sda");
+        lsblk
+            .exec
+            .expect_run()
+            .withf(move |arg: &Command| arg == &device_cmd)
+            .times(1)
+            .returning(|_: &Command| {
+                Ok(Vec::from(
+                    r#"{"blockdevices": [{"path":"/dev/sda", "name":"/dev/sda", "size":512110190592, "subsystems":"block:scsi:pci", "fstype":"btrfs", "label":"1d1e7a1c-5d43-4c2c-9d2f-4b0e8c1f4b8e", "rota":false}]}"#,
+                ))
+            });
+
+        lsblk.format(device, Filesystem::Btrfs, true).await.unwrap();
+        lsblk.exec.checkpoint();
+
+        lsblk
+            .exec
+            .expect_run()
+            .withf(move |arg: &Command| arg == &list_cmd)
+            .times(1)
+            .returning(|_: &Command| Ok(Vec::from(LSBLK_LIST_VALID)));
+
+        lsblk
+            .labeled("5ecdbb3c-b687-4048-b505-7a6756c2de76")
+            .await
+            .unwrap();
+        lsblk.exec.checkpoint();
+    }
+
+    #[tokio::test]
+    async fn lsblk_labeled_invalidate() {
+        const LABEL: &str = "5ecdbb3c-b687-4048-b505-7a6756c2de76";
+
+        let mut exec = crate::system::MockExecutor::default();
+        exec.expect_run()
+            .times(1)
+            .returning(|_: &Command| Ok(Vec::from(LSBLK_LIST_VALID)));
+
+        let mut lsblk = LsBlk::new(exec).with_label_cache();
+        let device = lsblk.labeled(LABEL).await.unwrap();
+        assert_eq!(device.path(), Path::new("/dev/sdb"));
+        lsblk.exec.checkpoint();
+
+        // the disk was swapped, and its label now shows up on another node
+        lsblk.exec.expect_run().times(1).returning(|_: &Command| {
+            Ok(Vec::from(
+                r#"{"blockdevices": [{"path":"/dev/sde", "name":"/dev/sde", "size":3000592982016, "subsystems":"block:scsi:pci", "fstype":"btrfs", "label":"5ecdbb3c-b687-4048-b505-7a6756c2de76", "rota":true}]}"#,
+            ))
+        });
+
+        lsblk.invalidate();
+        let device = lsblk.labeled(LABEL).await.unwrap();
+        assert_eq!(device.path(), Path::new("/dev/sde"));
+        lsblk.exec.checkpoint();
+    }
+
+    #[tokio::test]
     async fn lsblk_format() {
-        //todo!
+        const LSBLK_DEVICE_EMPTY: &str = r#"{
+            "blockdevices": [
+               {"path":"/dev/sdd", "name":"/dev/sdd", "size":3000592982016, "subsystems":"block:scsi:pci", "fstype": null, "label": null, "rota":false}
+            ]
+         }"#;
+
+        const LSBLK_DEVICE_FORMATTED: &str = r#"{
+            "blockdevices": [
+               {"path":"/dev/sdd", "name":"/dev/sdd", "size":3000592982016, "subsystems":"block:scsi:pci", "fstype": "btrfs", "label": "1d1e7a1c-5d43-4c2c-9d2f-4b0e8c1f4b8e", "rota":false}
+            ]
+         }"#;
+
+        let mut exec = crate::system::MockExecutor::default();
+        let lsblk_cmd = Command::new("lsblk")
+            .arg("--json")
+            .arg("-o")
+            .arg("PATH,NAME,SIZE,SUBSYSTEMS,FSTYPE,LABEL,ROTA")
+            .arg("--bytes")
+            .arg("--exclude")
+            .arg("1,2,11")
+            .arg("/dev/sdd");
+
+        let cmd = lsblk_cmd.clone();
+        exec.expect_run()
+            .withf(move |arg: &Command| arg == &cmd)
+            .times(1)
+            .returning(|_: &Command| Ok(Vec::from(LSBLK_DEVICE_EMPTY)));
+
+        let mut lsblk = LsBlk::new(exec);
+        let device = lsblk.device("/dev/sdd").await.unwrap();
+        lsblk.exec.checkpoint();
+        assert!(device.filesystem().is_none());
+
+        lsblk
+            .exec
+            .expect_run()
+            .withf(|arg: &Command| {
+                let cmd = arg.to_string();
+                cmd.starts_with(r#""mkfs.btrfs" "-f" "-L" "#) && cmd.ends_with(r#" "/dev/sdd""#)
+            })
+            .times(1)
+            .returning(|_: &Command| Ok(Vec::default()));
+
+        lsblk
+            .exec
+            .expect_run()
+            .withf(move |arg: &Command| arg == &lsblk_cmd)
+            .times(1)
+            .returning(|_: &Command| Ok(Vec::from(LSBLK_DEVICE_FORMATTED)));
+
+        let device = lsblk.format(device, Filesystem::Btrfs, true).await.unwrap();
+        lsblk.exec.checkpoint();
+
+        assert!(matches!(device.filesystem(), Some(f) if f == "btrfs"));
+        assert!(device.label().is_some());
+    }
+
+    #[tokio::test]
+    async fn lsblk_format_ext4() {
+        const LSBLK_DEVICE_FORMATTED: &str = r#"{
+            "blockdevices": [
+               {"path":"/dev/sdd", "name":"/dev/sdd", "size":3000592982016, "subsystems":"block:scsi:pci", "fstype": "ext4", "label": "1d1e7a1c5d434c2c", "rota":true}
+            ]
+         }"#;
+
+        let mut exec = crate::system::MockExecutor::default();
+        let lsblk_cmd = Command::new("lsblk")
+            .arg("--json")
+            .arg("-o")
+            .arg("PATH,NAME,SIZE,SUBSYSTEMS,FSTYPE,LABEL,ROTA")
+            .arg("--bytes")
+            .arg("--exclude")
+            .arg("1,2,11")
+            .arg("/dev/sdd");
+
+        exec.expect_run()
+            .withf(|arg: &Command| {
+                let cmd = arg.to_string();
+                // "mkfs.ext4" "-L" "<16 chars label>" "/dev/sdd"
+                cmd.starts_with(r#""mkfs.ext4" "-L" "#)
+                    && cmd.ends_with(r#" "/dev/sdd""#)
+                    && cmd.len() == r#""mkfs.ext4" "-L" "" "/dev/sdd""#.len() + 16
+            })
+            .times(1)
+            .returning(|_: &Command| Ok(Vec::default()));
+
+        exec.expect_run()
+            .withf(move |arg: &Command| arg == &lsblk_cmd)
+            .times(1)
+            .returning(|_: &Command| Ok(Vec::from(LSBLK_DEVICE_FORMATTED)));
+
+        let mut lsblk = LsBlk::new(exec);
+        let device: super::LsblkDevice =
+            serde_json::from_str(r#"{"path":"/dev/sdd", "size":3000592982016, "subsystems":"block:scsi:pci", "fstype": null, "label": null, "rota":true}"#)
+                .unwrap();
+
+        let device = lsblk.format(device, Filesystem::Ext4, false).await.unwrap();
+        lsblk.exec.checkpoint();
+
+        assert!(matches!(device.filesystem(), Some(f) if f == "ext4"));
+        assert!(matches!(device.label(), Some(l) if l.len() == 16));
     }
 }
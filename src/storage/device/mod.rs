@@ -4,6 +4,7 @@ use serde::{Deserialize, Serialize};
 use std::fmt::Display;
 use std::path::Path;
 use std::str::FromStr;
+use std::time::Duration;
 
 pub mod lsblk;
 pub use lsblk::{LsBlk, LsblkDevice};
@@ -25,6 +26,14 @@ impl Display for DeviceType {
     }
 }
 
+/// result of a device type detection with the measured
+/// average seek latency
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct SeekResult {
+    pub device_type: DeviceType,
+    pub avg_latency: Duration,
+}
+
 impl FromStr for DeviceType {
     type Err = &'static str;
 
@@ -40,12 +49,14 @@ impl FromStr for DeviceType {
 #[derive(Clone, Debug)]
 pub enum Filesystem {
     Btrfs,
+    Ext4,
 }
 
 impl Display for Filesystem {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Self::Btrfs => write!(f, "btrfs"),
+            Self::Ext4 => write!(f, "ext4"),
         }
     }
 }
@@ -77,14 +88,34 @@ pub trait DeviceManager: Send + Sync {
 
     async fn shutdown(&self, device: &Self::Device) -> Result<()>;
 
+    /// spin down an unused device to save power. by default
+    /// it's the same as shutdown
+    async fn power_off(&self, device: &Self::Device) -> Result<()> {
+        self.shutdown(device).await
+    }
+
     async fn seektime(&self, device: &Self::Device) -> Result<DeviceType>;
 
+    /// same as seektime but also returns the measured latency. by default
+    /// it delegates to seektime and reports no latency
+    async fn seektime_detailed(&self, device: &Self::Device) -> Result<SeekResult> {
+        Ok(SeekResult {
+            device_type: self.seektime(device).await?,
+            avg_latency: Duration::ZERO,
+        })
+    }
+
     async fn format(
         &self,
         device: Self::Device,
         filesystem: Filesystem,
         force: bool,
     ) -> Result<Self::Device>;
+
+    /// drop any cached device information. must be called when devices
+    /// might have changed (say a disk was hot swapped) so a label is not
+    /// resolved to a stale device path. by default there is nothing cached
+    fn invalidate(&self) {}
 }
 
 #[cfg(test)]
@@ -144,12 +175,16 @@ pub mod test {
             unimplemented!()
         }
 
-        async fn labeled<S: AsRef<str> + Send>(&self, _label: S) -> Result<Self::Device> {
-            unimplemented!()
+        async fn labeled<S: AsRef<str> + Send>(&self, label: S) -> Result<Self::Device> {
+            self.devices
+                .iter()
+                .find(|d| matches!(&d.label, Some(l) if l == label.as_ref()))
+                .cloned()
+                .ok_or_else(|| anyhow::anyhow!("device not found"))
         }
 
         async fn shutdown(&self, _device: &Self::Device) -> Result<()> {
-            unimplemented!()
+            Ok(())
         }
 
         async fn seektime(&self, device: &Self::Device) -> Result<DeviceType> {
@@ -3,18 +3,32 @@ use super::pool;
 use super::pool::State;
 use super::pool::{Pool, PoolManager};
 use super::Result;
-use super::{DeviceInfo, DiskInfo, VolumeInfo};
+use super::{
+    DeviceInfo, DiskInfo, DiskInspect, DiskMode, Manager, PoolInfo, StorageHealth, Usage,
+    VolumeInfo,
+};
 use crate::cache::Store;
 use crate::storage::device::{DeviceManager, DeviceType};
+use crate::storage::is_mounted;
 use crate::storage::pool::{DownPool, UpPool, Volume};
+use crate::system::Syscalls;
 use crate::Unit;
 use anyhow::Context;
+use nix::mount::MsFlags;
+use std::collections::HashSet;
 use std::os::unix::io::AsRawFd;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 use tokio::fs::OpenOptions;
 
 const VDISKS_VOLUME: &str = "vdisks";
 const ZDB_VOLUME: &str = "zdb";
+const CACHE_VOLUME: &str = "zos-cache";
+const CACHE_TARGET: &str = "/var/cache";
+const CACHE_SIZE: Unit = 100 * crate::GIGABYTE;
+// version of the format of the cached device types, must be changed
+// if the DeviceType string representation changes
+const CACHE_FORMAT_VERSION: semver::Version = semver::Version::new(1, 0, 0);
 
 pub struct StorageManager<M, P, U, D>
 where
@@ -30,6 +44,29 @@ where
     cache: Store<DeviceType>,
     ssd_size: Unit,
     hdd_size: Unit,
+    // pools (or devices) that failed to initialize with the error
+    broken: Vec<(String, String)>,
+    // spin down hdd devices when their pools are brought down
+    spindown: bool,
+}
+
+/// Options of the storage manager startup
+#[derive(Debug, Clone)]
+pub struct Options {
+    /// spin down hdd devices when their pools are brought down
+    pub spindown: bool,
+    /// verify (and repair) the vdisks volume of the ssd pools
+    /// once the manager is initialized
+    pub verify: bool,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            spindown: true,
+            verify: false,
+        }
+    }
 }
 
 impl<M, P, U, D> StorageManager<M, P, U, D>
@@ -40,6 +77,22 @@ where
     D: DownPool<UpPool = U>,
 {
     pub async fn new(device_mgr: M, pool_mgr: P) -> Result<Self> {
+        Self::with_options(device_mgr, pool_mgr, Options::default()).await
+    }
+
+    /// same as new but allows disabling spinning down of unused
+    /// hdd devices (say in datacenters)
+    pub async fn with_spindown(device_mgr: M, pool_mgr: P, spindown: bool) -> Result<Self> {
+        let options = Options {
+            spindown,
+            ..Default::default()
+        };
+
+        Self::with_options(device_mgr, pool_mgr, options).await
+    }
+
+    /// same as new but with all startup options
+    pub async fn with_options(device_mgr: M, pool_mgr: P, options: Options) -> Result<Self> {
         let mut this = Self {
             device_mgr,
             pool_mgr,
@@ -47,17 +100,158 @@ where
             hdds: Vec::default(),
             cache: Store::new("storage", crate::MEGABYTE)
                 .await
-                .context("failed to initialize storage disk type cache")?,
+                .context("failed to initialize storage disk type cache")?
+                .versioned(&CACHE_FORMAT_VERSION)
+                .await
+                .context("failed to check storage disk type cache version")?,
             ssd_size: 0,
             hdd_size: 0,
+            broken: Vec::default(),
+            spindown: options.spindown,
         };
 
         this.initialize().await?;
 
+        if options.verify {
+            let repaired = this.verify(true).await?;
+            if !repaired.is_empty() {
+                log::warn!("recreated missing vdisks volume on pools: {:?}", repaired);
+            }
+        }
+
         // setup cache partition
         Ok(this)
     }
 
+    /// pools (or devices) that failed to come up during initialization
+    /// as (pool name or device path, error) pairs
+    pub fn broken_pools(&self) -> &[(String, String)] {
+        &self.broken
+    }
+
+    /// total size of all pools of the given device type
+    pub fn total_space(&self, typ: DeviceType) -> Unit {
+        match typ {
+            DeviceType::SSD => self.ssd_size,
+            DeviceType::HDD => self.hdd_size,
+        }
+    }
+
+    /// free space left on all pools of the given device type. All space
+    /// of a down pool is free. hdd pools are allocated as a whole, so an
+    /// up hdd pool has no free space.
+    pub async fn free_space(&self, typ: DeviceType) -> Result<Unit> {
+        let mut free: Unit = 0;
+        match typ {
+            DeviceType::SSD => {
+                for pool in self.ssds.iter() {
+                    free += match pool {
+                        Pool::Up(up) => up.usage().await?.remaining(),
+                        _ => pool.size(),
+                    };
+                }
+            }
+            DeviceType::HDD => {
+                for pool in self.hdds.iter() {
+                    if pool.state() == State::Down {
+                        free += pool.size();
+                    }
+                }
+            }
+        }
+
+        Ok(free)
+    }
+
+    /// verify checks that all up ssd pools have the vdisks volume. Returns
+    /// the names of the pools where it's missing. If repair is set, the
+    /// missing volumes are created again. Like in disk_create the vdisks
+    /// volume has no size limit, each disk is allocated its own space.
+    pub async fn verify(&self, repair: bool) -> Result<Vec<String>> {
+        let mut missing = vec![];
+        for pool in self.ssds.iter() {
+            let up = match pool {
+                Pool::Up(up) => up,
+                _ => continue,
+            };
+
+            match up.volume(VDISKS_VOLUME).await {
+                Ok(_) => continue,
+                Err(pool::Error::VolumeNotFound { .. }) => (),
+                Err(err) => return Err(err.into()),
+            };
+
+            log::error!(
+                "pool '{}' is missing the '{}' volume",
+                up.name(),
+                VDISKS_VOLUME
+            );
+
+            if repair {
+                let vol = up.volume_create(VDISKS_VOLUME).await?;
+                vol.limit(None).await?;
+            }
+
+            missing.push(up.name().into());
+        }
+
+        Ok(missing)
+    }
+
+    /// ensure_cache makes sure the zos-cache volume exists and is mounted
+    /// under /var/cache. Returns the path of the mounted cache.
+    pub async fn ensure_cache<S: Syscalls>(&mut self, sys: &S) -> Result<PathBuf> {
+        self.mount_cache(sys, CACHE_TARGET).await
+    }
+
+    async fn mount_cache<S: Syscalls, T: AsRef<Path>>(
+        &mut self,
+        sys: &S,
+        target: T,
+    ) -> Result<PathBuf> {
+        let target = target.as_ref();
+        if is_mounted(target).await? {
+            return Ok(target.into());
+        }
+
+        // volume_create returns the volume if it already exists
+        let volume = self.volume_create(CACHE_VOLUME, CACHE_SIZE).await?;
+
+        tokio::fs::create_dir_all(target)
+            .await
+            .with_context(|| format!("failed to create cache directory: {:?}", target))?;
+
+        super::mount::mount(
+            sys,
+            Some(&volume.path),
+            target,
+            None::<&str>,
+            MsFlags::MS_BIND,
+            None::<&str>,
+        )
+        .with_context(|| format!("failed to mount cache volume on: {:?}", target))?;
+
+        Ok(target.into())
+    }
+
+    // power off the device of an hdd pool (by label) if spindown is enabled.
+    // errors are only logged since the pool is already down
+    async fn power_off<S: AsRef<str>>(&self, label: S) {
+        if !self.spindown {
+            return;
+        }
+
+        let label = label.as_ref();
+        let result = match self.device_mgr.labeled(label).await {
+            Ok(device) => self.device_mgr.power_off(&device).await,
+            Err(err) => Err(err),
+        };
+
+        if let Err(err) = result {
+            log::error!("failed to power off device of pool '{}': {}", label, err);
+        }
+    }
+
     async fn get_type(&self, device: &M::Device) -> Result<DeviceType> {
         // first check cache
         let name = match device.path().file_name() {
@@ -76,7 +270,14 @@ where
         }
 
         // if not set, then we need to use the seektime to get and set it
-        let t = self.device_mgr.seektime(device).await?;
+        let result = self.device_mgr.seektime_detailed(device).await?;
+        log::info!(
+            "detected device '{:?}' type: {} (average seek latency: {:?})",
+            device.path(),
+            result.device_type,
+            result.avg_latency
+        );
+        let t = result.device_type;
         self.cache.set(name, &t).await.with_context(|| {
             format!("failed to cache detected device type: {:?}", device.path())
         })?;
@@ -99,48 +300,7 @@ where
     async fn initialize(&mut self) -> Result<()> {
         let devices = self.device_mgr.devices().await?;
         for device in devices {
-            let device_typ = match self.get_type(&device).await {
-                Ok(typ) => typ,
-                Err(err) => {
-                    log::error!(
-                        "failed to detect device '{:?}' type: {}",
-                        device.path(),
-                        err
-                    );
-                    continue;
-                }
-            };
-
-            let mut pool = match self.pool_mgr.get(&self.device_mgr, device).await {
-                Ok(pool) => pool,
-                Err(err) => {
-                    log::error!("failed to initialize pool for device: {}", err);
-                    // store error for reference ?
-                    continue;
-                }
-            };
-
-            let usage = match self.validate(&mut pool).await {
-                Ok(usage) => usage,
-                Err(err) => {
-                    // invalid pool
-                    log::error!("failed to validate pool '{}': {}", pool.name(), err);
-                    // add to broken pools list.
-                    continue;
-                }
-            };
-
-            // todo: clean up hdd disks
-            match device_typ {
-                DeviceType::SSD => {
-                    self.ssd_size += usage.size;
-                    self.ssds.push(pool);
-                }
-                DeviceType::HDD => {
-                    self.hdd_size += usage.size;
-                    self.hdds.push(pool);
-                }
-            };
+            self.add_device(device).await;
         }
 
         // not at this point all pools are "created" but not all of them
@@ -149,7 +309,124 @@ where
         Ok(())
     }
 
+    // add_device creates the pool of the device and adds it to the known pools.
+    // returns the pool name, or None if the device is broken.
+    async fn add_device(&mut self, device: M::Device) -> Option<String> {
+        let path = device.path().to_string_lossy().to_string();
+        let device_typ = match self.get_type(&device).await {
+            Ok(typ) => typ,
+            Err(err) => {
+                log::error!("failed to detect device '{}' type: {}", path, err);
+                self.broken.push((path, err.to_string()));
+                return None;
+            }
+        };
+
+        let mut pool = match self.pool_mgr.get(&self.device_mgr, device).await {
+            Ok(pool) => pool,
+            Err(err) => {
+                log::error!("failed to initialize pool for device '{}': {}", path, err);
+                self.broken.push((path, err.to_string()));
+                return None;
+            }
+        };
+
+        let usage = match self.validate(&mut pool).await {
+            Ok(usage) => usage,
+            Err(err) => {
+                // invalid pool
+                log::error!("failed to validate pool '{}': {}", pool.name(), err);
+                self.broken.push((pool.name().into(), err.to_string()));
+                return None;
+            }
+        };
+
+        let name = pool.name().to_string();
+        // todo: clean up hdd disks
+        match device_typ {
+            DeviceType::SSD => {
+                self.ssd_size += usage.size;
+                self.ssds.push(pool);
+            }
+            DeviceType::HDD => {
+                if pool.state() == State::Down {
+                    self.power_off(pool.name()).await;
+                }
+
+                self.hdd_size += usage.size;
+                self.hdds.push(pool);
+            }
+        };
+
+        Some(name)
+    }
+
+    /// rescan lists the devices again and creates pools for devices that
+    /// showed up since the last scan (hot plugged). Known pools are matched
+    /// by label and broken devices are not retried. Pools of devices that
+    /// are gone are only logged. Returns the names of the new pools.
+    pub async fn rescan(&mut self) -> Result<Vec<String>> {
+        // devices might have been swapped since the last scan, so cached
+        // labels can point to the wrong device
+        self.device_mgr.invalidate();
+        let devices = self.device_mgr.devices().await?;
+        let mut seen = HashSet::new();
+        let mut added = vec![];
+        for device in devices {
+            let path = device.path().to_string_lossy().to_string();
+            let label = device.label().map(String::from);
+            if let Some(label) = &label {
+                seen.insert(label.clone());
+            }
+
+            let known = |name: &str| label.as_deref() == Some(name) || name == path;
+            if self
+                .ssds
+                .iter()
+                .chain(self.hdds.iter())
+                .any(|pool| known(pool.name()))
+                || self.broken.iter().any(|(name, _)| known(name))
+            {
+                continue;
+            }
+
+            log::info!("found new device '{}'", path);
+            if let Some(name) = self.add_device(device).await {
+                seen.insert(name.clone());
+                added.push(name);
+            }
+        }
+
+        for pool in self.ssds.iter().chain(self.hdds.iter()) {
+            if !seen.contains(pool.name()) {
+                log::warn!("device of pool '{}' is gone", pool.name());
+            }
+        }
+
+        Ok(added)
+    }
+
+    /// watch rescans the devices every interval to pick up hot plugged
+    /// devices. It never returns.
+    pub async fn watch(&mut self, interval: Duration) {
+        let mut ticker = tokio::time::interval(interval);
+        // first tick completes immediately, devices were already
+        // scanned on initialization
+        ticker.tick().await;
+        loop {
+            ticker.tick().await;
+            match self.rescan().await {
+                Ok(added) if !added.is_empty() => log::info!("added new pools: {:?}", added),
+                Ok(_) => (),
+                Err(err) => log::error!("failed to rescan devices: {}", err),
+            }
+        }
+    }
+
     // find an pool with free size. possibly bringing some pools up.
+    // allocation takes &mut self so callers sharing the manager need to
+    // hold a lock across allocate and volume creation, otherwise two
+    // callers can see the same free space.
     async fn allocate(&mut self, size: Unit) -> Result<&U> {
         let mut index = None;
         for (i, pool) in self.ssds.iter().enumerate() {
@@ -192,7 +469,7 @@ where
 }
 
 #[async_trait::async_trait]
-impl<M, P, U, D> super::Manager for StorageManager<M, P, U, D>
+impl<M, P, U, D> Manager for StorageManager<M, P, U, D>
 where
     M: DeviceManager,
     P: PoolManager<M, U, D>,
@@ -256,6 +533,36 @@ where
     }
 
     async fn volume_delete<S: AsRef<str> + Send + Sync>(&self, name: S) -> Result<()> {
+        self.volume_delete_force(name, true).await
+    }
+
+    async fn volume_delete_force<S: AsRef<str> + Send + Sync>(
+        &self,
+        name: S,
+        force: bool,
+    ) -> Result<()> {
+        if !force {
+            for pool in self.ssds.iter() {
+                let up = match pool {
+                    Pool::Up(up) => up,
+                    _ => continue,
+                };
+
+                let vol = match up.volume(&name).await {
+                    Ok(vol) => vol,
+                    Err(pool::Error::VolumeNotFound { .. }) => continue,
+                    Err(err) => return Err(err.into()),
+                };
+
+                let mut entries = tokio::fs::read_dir(vol.path()).await?;
+                if entries.next_entry().await?.is_some() {
+                    return Err(super::Error::NotEmpty {
+                        volume: name.as_ref().into(),
+                    });
+                }
+            }
+        }
+
         for pool in self.ssds.iter() {
             let up = match pool {
                 Pool::Up(up) => up,
@@ -275,7 +582,125 @@ where
         Ok(())
     }
 
+    async fn volume_rename<S: AsRef<str> + Send + Sync, N: AsRef<str> + Send + Sync>(
+        &self,
+        old: S,
+        new: N,
+    ) -> Result<()> {
+        validate_disk_name(&new)?;
+        match self.volume_lookup(&new).await {
+            Ok(_) => {
+                return Err(pool::Error::VolumeAlreadyExists {
+                    volume: new.as_ref().into(),
+                }
+                .into())
+            }
+            Err(super::Error::NotFound { .. }) => (),
+            Err(err) => return Err(err),
+        };
+
+        for pool in self.ssds.iter() {
+            let up = match pool {
+                Pool::Up(up) => up,
+                _ => continue,
+            };
+
+            match up.volume(&old).await {
+                Ok(vol) => return Ok(vol.rename(&new).await?),
+                Err(pool::Error::VolumeNotFound { .. }) => continue,
+                Err(err) => return Err(err.into()),
+            }
+        }
+
+        Err(super::Error::NotFound {
+            id: old.as_ref().into(),
+            kind: super::Kind::Volume,
+        })
+    }
+
+    async fn volume_migrate<S: AsRef<str> + Send + Sync>(
+        &mut self,
+        name: S,
+        target_pool: S,
+    ) -> Result<VolumeInfo> {
+        let name = name.as_ref();
+        let target_pool = target_pool.as_ref();
+
+        // find the source volume, only up pools can have volumes
+        let mut source = None;
+        for pool in self.ssds.iter() {
+            let up = match pool {
+                Pool::Up(up) => up,
+                _ => continue,
+            };
+
+            match up.volume(name).await {
+                Ok(vol) => {
+                    source = Some((
+                        up.name().to_string(),
+                        vol.path().to_owned(),
+                        vol.usage().await?,
+                        vol.quota().await?,
+                    ));
+                    break;
+                }
+                Err(pool::Error::VolumeNotFound { .. }) => continue,
+                Err(err) => return Err(err.into()),
+            }
+        }
+
+        let (source_pool, source_path, size, quota) =
+            source.ok_or_else(|| super::Error::NotFound {
+                id: name.into(),
+                kind: super::Kind::Volume,
+            })?;
+
+        let target = self
+            .ssds
+            .iter_mut()
+            .find(|pool| pool.name() == target_pool)
+            .ok_or_else(|| super::Error::NotFound {
+                id: target_pool.into(),
+                kind: super::Kind::Pool,
+            })?;
+
+        if source_pool == target_pool {
+            let vol = target.as_up().volume(name).await?;
+            return Ok((&vol).into());
+        }
+
+        let up = target.into_up().await?;
+        if !up.usage().await?.enough_for(size) {
+            return Err(super::Error::NoEnoughSpaceLeft);
+        }
+
+        let vol = up.volume_create(name).await?;
+        if let Err(err) = copy_volume(&source_path, vol.path(), quota, &vol).await {
+            // clean up the partial copy, source is still intact
+            if let Err(err) = up.volume_delete(name).await {
+                log::error!(
+                    "failed to clean up volume '{}' on pool '{}': {}",
+                    name,
+                    target_pool,
+                    err
+                );
+            }
+            return Err(err);
+        }
+        let info = VolumeInfo::from(&vol);
+
+        for pool in self.ssds.iter() {
+            if pool.name() == source_pool {
+                pool.as_up().volume_delete(name).await?;
+                break;
+            }
+        }
+
+        Ok(info)
+    }
+
     async fn disk_lookup<S: AsRef<str> + Send + Sync>(&self, name: S) -> Result<DiskInfo> {
+        validate_disk_name(&name)?;
         for pool in self.ssds.iter() {
             let up = match pool {
                 Pool::Up(up) => up,
@@ -291,13 +716,9 @@ where
                 }
             };
 
-            //todo: is it a safe path? beware of path injection like "../name"
             let path = vol.path().join(name.as_ref());
             if let Ok(meta) = tokio::fs::metadata(&path).await {
-                return Ok(DiskInfo {
-                    path,
-                    size: meta.len(),
-                });
+                return Ok(disk_info(path, &meta));
             }
         }
 
@@ -311,7 +732,9 @@ where
         &mut self,
         name: S,
         size: Unit,
+        mode: DiskMode,
     ) -> Result<DiskInfo> {
+        validate_disk_name(&name)?;
         match self.disk_lookup(&name).await {
             Ok(disk) => return Ok(disk),
             Err(super::Error::NotFound { .. }) => (),
@@ -327,9 +750,15 @@ where
         };
 
         let path = vol.path().join(name.as_ref());
-        mkdisk(&path, size).await?;
+        if !mkdisk(&path, size, mode).await? {
+            log::warn!(
+                "disk '{}' was created with copy on write enabled",
+                name.as_ref()
+            );
+        }
+        let meta = tokio::fs::metadata(&path).await?;
 
-        Ok(DiskInfo { path, size })
+        Ok(disk_info(path, &meta))
     }
 
     async fn disks(&self) -> Result<Vec<DiskInfo>> {
@@ -368,10 +797,7 @@ where
                     continue;
                 }
 
-                disks.push(DiskInfo {
-                    path,
-                    size: meta.len(),
-                });
+                disks.push(disk_info(path, &meta));
             }
         }
 
@@ -379,6 +805,7 @@ where
     }
 
     async fn disk_delete<S: AsRef<str> + Send + Sync>(&self, name: S) -> Result<()> {
+        validate_disk_name(&name)?;
         let disk = match self.disk_lookup(&name).await {
             Ok(disk) => disk,
             Err(super::Error::NotFound { .. }) => return Ok(()),
@@ -392,6 +819,7 @@ where
 
     async fn disk_expand<S: AsRef<str> + Send + Sync>(&self, name: S, size: Unit) -> Result<()> {
         // expand disk size
+        validate_disk_name(&name)?;
         let disk = self.disk_lookup(name).await?;
 
         use std::cmp::Ordering;
@@ -401,7 +829,26 @@ where
             _ => (),
         };
 
-        mkdisk(disk.path, size).await
+        // keep sparse disks sparse
+        let mode = if disk.used < disk.size {
+            DiskMode::Sparse
+        } else {
+            DiskMode::Preallocated
+        };
+
+        expand_disk(disk.path, size, mode).await
+    }
+
+    async fn disk_inspect<S: AsRef<str> + Send + Sync>(&self, name: S) -> Result<DiskInspect> {
+        validate_disk_name(&name)?;
+        let disk = self.disk_lookup(name).await?;
+        let detected_fs = detect_fs(&disk.path).await?;
+
+        Ok(DiskInspect {
+            size: disk.size,
+            used: disk.used,
+            detected_fs: detected_fs.map(String::from),
+        })
     }
 
     // devices
@@ -431,6 +878,34 @@ where
         Err(super::Error::NoDeviceLeft)
     }
 
+    async fn device_release<S: AsRef<str> + Send + Sync>(&mut self, name: S) -> Result<()> {
+        let pool = match self.hdds.iter_mut().find(|p| p.name() == name.as_ref()) {
+            Some(pool) => pool,
+            None => {
+                return Err(super::Error::NotFound {
+                    id: name.as_ref().into(),
+                    kind: super::Kind::Device,
+                })
+            }
+        };
+
+        let empty = match pool {
+            Pool::Up(up) => {
+                up.volume_delete(ZDB_VOLUME).await?;
+                up.volumes().await?.is_empty()
+            }
+            // a down pool has nothing allocated
+            _ => return Ok(()),
+        };
+
+        if empty {
+            pool.into_down().await?;
+            self.power_off(name).await;
+        }
+
+        Ok(())
+    }
+
     async fn devices(&self) -> Result<Vec<DeviceInfo>> {
         let mut devices = vec![];
         for pool in self.hdds.iter() {
@@ -460,6 +935,91 @@ where
         Ok(devices)
     }
 
+    async fn maintenance(&self) -> Result<()> {
+        for pool in self.ssds.iter().chain(self.hdds.iter()) {
+            let up = match pool {
+                Pool::Up(up) => up,
+                _ => continue,
+            };
+
+            if let Err(err) = up.maintenance().await {
+                log::error!("failed to run maintenance on pool '{}': {}", up.name(), err);
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn health(&self) -> Result<StorageHealth> {
+        let mut health = StorageHealth {
+            broken: self.broken.iter().map(|(name, _)| name.clone()).collect(),
+            ..Default::default()
+        };
+        health.ssd.size = self.ssd_size;
+        health.hdd.size = self.hdd_size;
+
+        for pool in self.ssds.iter() {
+            let up = match pool {
+                Pool::Up(up) => up,
+                _ => {
+                    health.pools_down += 1;
+                    continue;
+                }
+            };
+
+            health.pools_up += 1;
+            match up.usage().await {
+                Ok(usage) => health.ssd.used += usage.used,
+                Err(err) => {
+                    log::error!("failed to get pool '{}' usage: {}", up.name(), err);
+                    health.broken.push(up.name().into());
+                }
+            }
+        }
+
+        // hdd pools are allocated as a whole
+        for pool in self.hdds.iter() {
+            match pool.state() {
+                State::Up => {
+                    health.pools_up += 1;
+                    health.hdd.used += pool.size();
+                }
+                State::Down => health.pools_down += 1,
+            }
+        }
+
+        Ok(health)
+    }
+
+    async fn pools(&self) -> Result<Vec<PoolInfo>> {
+        let ssds = self.ssds.iter().map(|pool| (pool, DeviceType::SSD));
+        let hdds = self.hdds.iter().map(|pool| (pool, DeviceType::HDD));
+
+        let mut pools = vec![];
+        for (pool, device_type) in ssds.chain(hdds) {
+            let mut usage = Usage {
+                size: pool.size(),
+                used: 0,
+            };
+
+            if let Pool::Up(up) = pool {
+                match up.usage().await {
+                    Ok(u) => usage = u,
+                    Err(err) => log::error!("failed to get pool '{}' usage: {}", up.name(), err),
+                }
+            }
+
+            pools.push(PoolInfo {
+                name: pool.name().into(),
+                state: pool.state(),
+                device_type,
+                usage,
+            });
+        }
+
+        Ok(pools)
+    }
+
     async fn device_lookup<S: AsRef<str> + Send + Sync>(&self, name: S) -> Result<DeviceInfo> {
         for pool in self.hdds.iter() {
             let up = match pool {
@@ -496,7 +1056,160 @@ where
     }
 }
 
-async fn mkdisk<T: AsRef<Path>>(path: T, size: Unit) -> Result<()> {
+// copy_volume copies the content of the volume at source to the (new)
+// volume target. The target gets the same size limit as the source,
+// or no limit if the source had none.
+async fn copy_volume<V: Volume>(
+    source: &Path,
+    target: &Path,
+    quota: Option<Unit>,
+    vol: &V,
+) -> Result<()> {
+    if quota.is_some() {
+        vol.limit(quota).await?;
+    }
+
+    copy_dir(source, target)
+        .await
+        .with_context(|| format!("failed to copy volume {:?} to {:?}", source, target))?;
+
+    Ok(())
+}
+
+// copy_dir copies all files, directories and symlinks under source to target
+async fn copy_dir(source: &Path, target: &Path) -> std::io::Result<()> {
+    tokio::fs::create_dir_all(target).await?;
+    let mut dirs = vec![(source.to_owned(), target.to_owned())];
+    while let Some((source, target)) = dirs.pop() {
+        let mut entries = tokio::fs::read_dir(&source).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let to = target.join(entry.file_name());
+            let file_type = entry.file_type().await?;
+            if file_type.is_dir() {
+                tokio::fs::create_dir(&to).await?;
+                dirs.push((entry.path(), to));
+            } else if file_type.is_symlink() {
+                let link = tokio::fs::read_link(entry.path()).await?;
+                tokio::fs::symlink(link, &to).await?;
+            } else {
+                let from = entry.path();
+                tokio::task::spawn_blocking(move || copy_file(&from, &to)).await??;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// copy_file copies a regular file with its permissions. Copy on write is
+// disabled on the target if it's disabled on the source (vdisks), this must
+// be done before any data is written. Only the data ranges are copied so
+// holes in sparse files (say sparse vdisks) are kept.
+fn copy_file(source: &Path, target: &Path) -> std::io::Result<()> {
+    use nix::unistd::{lseek, Whence};
+    use std::io::{Read, Seek, SeekFrom};
+    use std::os::unix::fs::OpenOptionsExt;
+    use std::os::unix::fs::PermissionsExt;
+
+    let mut src = std::fs::File::open(source)?;
+    let meta = src.metadata()?;
+    let mut dst = std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(meta.permissions().mode())
+        .open(target)?;
+
+    if matches!(get_flags(&src), Ok(flags) if flags & FS_NOCOW_FL != 0) {
+        match set_nocow(&dst) {
+            Ok(true) => {}
+            Ok(false) => log::warn!("NOCOW flag was not applied to {:?}", target),
+            Err(err) => log::warn!("failed to set NOCOW flag on {:?}: {}", target, err),
+        }
+    }
+
+    let len = meta.len() as i64;
+    let mut offset = 0;
+    while offset < len {
+        let start = match lseek(src.as_raw_fd(), offset, Whence::SeekData) {
+            Ok(start) => start,
+            // no more data after offset, the rest is a hole
+            Err(nix::errno::Errno::ENXIO) => break,
+            Err(err) => return Err(err.into()),
+        };
+        let end = lseek(src.as_raw_fd(), start, Whence::SeekHole)?;
+
+        src.seek(SeekFrom::Start(start as u64))?;
+        dst.seek(SeekFrom::Start(start as u64))?;
+        std::io::copy(&mut (&mut src).take((end - start) as u64), &mut dst)?;
+        offset = end;
+    }
+
+    // trailing holes
+    dst.set_len(meta.len())?;
+
+    Ok(())
+}
+
+// validate_disk_name makes sure a disk name can't escape
+// the vdisks volume (say "../name")
+fn validate_disk_name<S: AsRef<str>>(name: S) -> Result<()> {
+    let name = name.as_ref();
+    if name.is_empty() || name.contains('/') || name.contains('\0') || name.contains("..") {
+        return Err(super::Error::InvalidName { name: name.into() });
+    }
+
+    Ok(())
+}
+
+// disk_info builds disk info from the disk file metadata
+fn disk_info(path: PathBuf, meta: &std::fs::Metadata) -> DiskInfo {
+    use std::os::unix::fs::MetadataExt;
+
+    DiskInfo {
+        size: meta.len(),
+        // blocks are always counted in 512 bytes units
+        used: meta.blocks() * 512,
+        nocow: std::fs::File::open(&path)
+            .ok()
+            .and_then(|file| get_flags(&file).ok())
+            .map(|flags| flags & FS_NOCOW_FL != 0)
+            .unwrap_or(false),
+        path,
+    }
+}
+
+const FS_NOCOW_FL: i64 = 0x00800000;
+
+fn get_flags<F: AsRawFd>(file: &F) -> std::result::Result<i64, nix::errno::Errno> {
+    let mut flags: i64 = 0;
+    let out = unsafe { ioctls::fs_ioc_getflags(file.as_raw_fd(), &mut flags) };
+    if out < 0 {
+        return Err(nix::errno::Errno::last());
+    }
+
+    Ok(flags)
+}
+
+// set_nocow disables copy on write on file, and reads back the inode flags to
+// make sure it's applied. Some filesystems ignore the flag, and it can't be set
+// on a file that already has data, so a failure here is only reported.
+fn set_nocow<F: AsRawFd>(file: &F) -> std::result::Result<bool, nix::errno::Errno> {
+    let flags = get_flags(file)?;
+    if flags & FS_NOCOW_FL != 0 {
+        return Ok(true);
+    }
+
+    let out = unsafe { ioctls::fs_ioc_setflags(file.as_raw_fd(), &(flags | FS_NOCOW_FL)) };
+    if out < 0 {
+        return Err(nix::errno::Errno::last());
+    }
+
+    Ok(get_flags(file)? & FS_NOCOW_FL != 0)
+}
+
+// mkdisk creates the disk file, returns true if copy on write was disabled
+// on the disk file
+async fn mkdisk<T: AsRef<Path>>(path: T, size: Unit, mode: DiskMode) -> Result<bool> {
     let file = OpenOptions::new()
         .create(true)
         .write(true)
@@ -505,23 +1218,123 @@ async fn mkdisk<T: AsRef<Path>>(path: T, size: Unit) -> Result<()> {
         .await
         .context("failed to create disk file")?;
 
-    if cfg!(not(test)) {
-        let out = unsafe {
-            const FS_NOCOW_FL: i64 = 0x00800000;
-            ioctls::fs_ioc_setflags(file.as_raw_fd(), &FS_NOCOW_FL)
-        };
+    let nocow = match set_nocow(&file) {
+        Ok(true) => true,
+        Ok(false) => {
+            log::warn!("NOCOW flag was not applied to disk {:?}", path.as_ref());
+            false
+        }
+        Err(err) => {
+            log::warn!(
+                "failed to set NOCOW flag on disk {:?}: {}",
+                path.as_ref(),
+                err
+            );
+            false
+        }
+    };
+
+    match mode {
+        DiskMode::Sparse => file
+            .set_len(size)
+            .await
+            .context("failed to set disk size")?,
+        DiskMode::Preallocated => {
+            use nix::fcntl::FallocateFlags;
+            // this is not async
+            nix::fcntl::fallocate(file.as_raw_fd(), FallocateFlags::empty(), 0, size as i64)
+                .context("failed to allocate required disk size")?;
+        }
+    };
+
+    Ok(nocow)
+}
+
+// expand_disk grows an existing disk file to size. Unlike mkdisk the file
+// must exist and only the added range is allocated, so the disk content is
+// never touched.
+async fn expand_disk<T: AsRef<Path>>(path: T, size: Unit, mode: DiskMode) -> Result<()> {
+    let file = OpenOptions::new()
+        .write(true)
+        .open(&path)
+        .await
+        .context("failed to open disk file")?;
+
+    let current = file
+        .metadata()
+        .await
+        .context("failed to get disk file size")?
+        .len();
+
+    if size < current {
+        return Err(super::Error::InvalidSize { size });
+    }
 
-        if out != 0 {
-            return Err(anyhow::anyhow!("failed to set NOCOW flag: {}", out).into());
+    match mode {
+        DiskMode::Sparse => file
+            .set_len(size)
+            .await
+            .context("failed to set disk size")?,
+        DiskMode::Preallocated => {
+            use nix::fcntl::FallocateFlags;
+            // this is not async
+            nix::fcntl::fallocate(
+                file.as_raw_fd(),
+                FallocateFlags::empty(),
+                current as i64,
+                (size - current) as i64,
+            )
+            .context("failed to allocate required disk size")?;
         }
     };
 
-    use nix::fcntl::FallocateFlags;
-    // this is not async
-    nix::fcntl::fallocate(file.as_raw_fd(), FallocateFlags::empty(), 0, size as i64)
-        .context("failed to allocate required disk size")?;
     Ok(())
 }
 
+// enough of the disk head to find the btrfs super block at 64KiB
+const DISK_HEADER_SIZE: u64 = 68 * 1024;
+
+// detect_fs reads the head of the disk file and looks for known signatures
+async fn detect_fs<T: AsRef<Path>>(path: T) -> Result<Option<&'static str>> {
+    use tokio::io::AsyncReadExt;
+
+    let file = tokio::fs::File::open(&path)
+        .await
+        .context("failed to open disk file")?;
+
+    let mut header = Vec::with_capacity(DISK_HEADER_SIZE as usize);
+    file.take(DISK_HEADER_SIZE)
+        .read_to_end(&mut header)
+        .await
+        .context("failed to read disk header")?;
+
+    Ok(detect_signature(&header))
+}
+
+// detect_signature checks the disk header for known magic values.
+// the header can be shorter than DISK_HEADER_SIZE for very small disks
+fn detect_signature(header: &[u8]) -> Option<&'static str> {
+    let at = |offset: usize, magic: &[u8]| {
+        header
+            .get(offset..offset + magic.len())
+            .map(|value| value == magic)
+            .unwrap_or(false)
+    };
+
+    // order matters, a gpt disk also has a protective mbr
+    if at(0x10040, b"_BHRfS_M") {
+        Some("btrfs")
+    } else if at(0x438, &[0x53, 0xef]) {
+        // ext2/3/4 share the same magic
+        Some("ext4")
+    } else if at(0x200, b"EFI PART") {
+        Some("gpt")
+    } else if at(0x1fe, &[0x55, 0xaa]) && (at(0x36, b"FAT") || at(0x52, b"FAT32")) {
+        Some("vfat")
+    } else {
+        None
+    }
+}
+
 #[cfg(test)]
 mod test;
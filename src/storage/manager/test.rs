@@ -1,7 +1,7 @@
-use super::StorageManager;
+use super::{Options, StorageManager};
 use crate::storage::device::{Device, DeviceManager};
 use crate::storage::{pool::*, Manager};
-use crate::storage::{Error as StorageError, Kind};
+use crate::storage::{DiskMode, Error as StorageError, Kind, VolumeInfo};
 use crate::Unit;
 use std::collections::HashMap;
 use std::path::{Path, PathBuf};
@@ -14,6 +14,7 @@ struct TestUpPool {
     pub path: PathBuf,
     pub size: Unit,
     pub volumes: Arc<Mutex<Vec<TestVolume>>>,
+    pub broken: bool,
 }
 
 #[derive(Clone)]
@@ -29,6 +30,9 @@ struct TestVolume {
     pub path: PathBuf,
     pub name: String,
     pub usage: Unit,
+    pub quota: Option<Unit>,
+    // volumes of the owning pool, set when the volume is returned by the pool
+    pub pool: Arc<Mutex<Vec<TestVolume>>>,
 }
 
 #[async_trait::async_trait]
@@ -49,13 +53,45 @@ impl Volume for TestVolume {
     }
 
     /// limit, set, update, or remove size limit of the volume
-    async fn limit(&self, _size: Option<Unit>) -> Result<()> {
+    async fn limit(&self, size: Option<Unit>) -> Result<()> {
+        // same as btrfs, a limited volume reports its limit as usage
+        let mut vols = self.pool.lock().await;
+        if let Some(vol) = vols.iter_mut().find(|v| v.name() == self.name) {
+            if let Some(size) = size {
+                vol.usage = size;
+            }
+            vol.quota = size;
+        }
+
         Ok(())
     }
 
     async fn usage(&self) -> Result<Unit> {
         Ok(self.usage)
     }
+
+    async fn quota(&self) -> Result<Option<Unit>> {
+        Ok(self.quota)
+    }
+
+    async fn rename<S: AsRef<str> + Send>(&self, new: S) -> Result<()> {
+        let new = new.as_ref();
+        let mut vols = self.pool.lock().await;
+        if vols.iter().any(|v| v.name() == new) {
+            return Err(Error::VolumeAlreadyExists { volume: new.into() });
+        }
+
+        let vol = vols
+            .iter_mut()
+            .find(|v| v.name() == self.name)
+            .ok_or_else(|| Error::VolumeNotFound {
+                volume: self.name.clone(),
+            })?;
+
+        vol.name = new.into();
+        vol.path = self.path.with_file_name(new);
+        Ok(())
+    }
 }
 
 #[async_trait::async_trait]
@@ -96,6 +132,10 @@ impl UpPool for TestUpPool {
 
     /// usage of the pool
     async fn usage(&self) -> Result<Usage> {
+        if self.broken {
+            return Err(Error::Other(anyhow::anyhow!("broken pool")));
+        }
+
         let mut used = 0;
         let vols = self.volumes.lock().await;
         for vol in vols.iter() {
@@ -112,8 +152,8 @@ impl UpPool for TestUpPool {
     async fn down(self) -> std::result::Result<Self::DownPool, DownError<Self>> {
         Ok(TestDownPool {
             name: self.name.clone(),
+            size: self.size,
             up: self,
-            size: 1 * crate::TERABYTE,
         })
     }
 
@@ -137,7 +177,10 @@ impl UpPool for TestUpPool {
 
         vols.push(vol.clone());
         // other wise just create
-        Ok(vol)
+        Ok(TestVolume {
+            pool: Arc::clone(&self.volumes),
+            ..vol
+        })
     }
 
     async fn volume<S: AsRef<str> + Send + Sync>(&self, name: S) -> Result<Self::Volume> {
@@ -156,7 +199,12 @@ impl UpPool for TestUpPool {
     /// list all volumes in the pool
     async fn volumes(&self) -> Result<Vec<Self::Volume>> {
         let v = self.volumes.lock().await;
-        Ok(v.clone())
+        Ok(v.iter()
+            .map(|vol| TestVolume {
+                pool: Arc::clone(&self.volumes),
+                ..vol.clone()
+            })
+            .collect())
     }
 
     /// delete volume pools
@@ -179,7 +227,12 @@ where
 {
     async fn get(&self, _manager: &M, device: M::Device) -> Result<Pool<TestUpPool, TestDownPool>> {
         //this should use the label, not the path.
-        let pool = self.map.get(device.path()).unwrap();
+        let pool = self
+            .map
+            .get(device.path())
+            .ok_or_else(|| Error::PoolNotFound {
+                pool: device.path().to_string_lossy().into(),
+            })?;
 
         Ok(pool.clone())
     }
@@ -236,6 +289,7 @@ async fn manager_initialize_basic() {
                 name: p1_label.clone(),
                 path: Path::new("/mnt").join(p1_label),
                 size: 1 * crate::TERABYTE,
+                broken: false,
                 volumes: Arc::default(),
             },
         }),
@@ -250,60 +304,861 @@ async fn manager_initialize_basic() {
                 name: p2_label.clone(),
                 path: Path::new("/mnt").join(&p2_label),
                 size: 1 * crate::TERABYTE,
+                broken: false,
                 volumes: Arc::new(Mutex::new(vec![TestVolume {
                     id: 0,
                     name: "zos-cache".into(),
                     path: Path::new("/mnt").join(p2_label).join("zos-cache"),
                     usage: 100 * crate::GIGABYTE,
+                    ..Default::default()
                 }])),
             },
         }),
     );
 
     pool_manager.map.insert(
-        p3_dev.clone(),
+        p3_dev.clone(),
+        Pool::Down(TestDownPool {
+            name: p3_label.clone(),
+            size: 4 * crate::TERABYTE,
+            up: TestUpPool {
+                name: p3_label.clone(),
+                path: Path::new("/mnt").join(p3_label),
+                size: 4 * crate::TERABYTE,
+                broken: false,
+                volumes: Arc::default(),
+            },
+        }),
+    );
+
+    let mgr = StorageManager::new(blk, pool_manager)
+        .await
+        .expect("manager failed to create");
+
+    assert_eq!(mgr.ssds.len(), 2);
+    assert_eq!(mgr.hdds.len(), 1);
+    assert_eq!(mgr.ssd_size, 2 * crate::TERABYTE);
+    assert_eq!(mgr.hdd_size, 4 * crate::TERABYTE);
+
+    let pool_1 = &mgr.ssds.iter().find(|p| p.name() == "pool-1").unwrap();
+    assert_eq!(pool_1.state(), State::Down);
+
+    let pool_2 = &mgr.ssds.iter().find(|p| p.name() == "pool-2").unwrap();
+    assert_eq!(pool_2.state(), State::Up);
+
+    let volumes = mgr.volumes().await.unwrap();
+    assert_eq!(volumes.len(), 1);
+
+    let cache_vol = &volumes[0];
+    assert_eq!(cache_vol.name, "zos-cache");
+    assert_eq!(cache_vol.path, Path::new("/mnt/pool-2/zos-cache"));
+
+    // find volume by name.
+    let vol = mgr.volume_lookup("zos-cache").await.unwrap();
+    assert_eq!(vol.name, "zos-cache");
+    assert_eq!(vol.path, Path::new("/mnt/pool-2/zos-cache"));
+
+    let errored = mgr.volume_lookup("not-found").await;
+
+    assert!(matches!(errored, Err(StorageError::NotFound { kind, .. }) if kind == Kind::Volume));
+}
+
+#[tokio::test]
+async fn manager_initialize_broken() {
+    use crate::storage::device::test::*;
+    use crate::storage::device::DeviceType;
+
+    let p1_dev: PathBuf = "/dev/test1".into();
+    let p1_label: String = "pool-1".into();
+
+    let p2_dev: PathBuf = "/dev/test2".into();
+    let p2_label: String = "pool-2".into();
+
+    // p3 has no pool associated with it
+    let p3_dev: PathBuf = "/dev/test3".into();
+
+    let blk = TestManager {
+        devices: vec![
+            TestDevice {
+                path: p1_dev.clone(),
+                device_type: DeviceType::SSD,
+                filesystem: Some("test".into()),
+                label: Some(p1_label.clone()),
+                size: 1 * crate::TERABYTE,
+            },
+            TestDevice {
+                path: p2_dev.clone(),
+                device_type: DeviceType::SSD,
+                filesystem: Some("test".into()),
+                label: Some(p2_label.clone()),
+                size: 1 * crate::TERABYTE,
+            },
+            TestDevice {
+                path: p3_dev.clone(),
+                device_type: DeviceType::HDD,
+                filesystem: None,
+                label: None,
+                size: 4 * crate::TERABYTE,
+            },
+        ],
+    };
+
+    let mut pool_manager = TestPoolManager::default();
+    pool_manager.map.insert(
+        p1_dev.clone(),
+        Pool::Down(TestDownPool {
+            name: p1_label.clone(),
+            size: 1 * crate::TERABYTE,
+            up: TestUpPool {
+                name: p1_label.clone(),
+                path: Path::new("/mnt").join(p1_label),
+                size: 1 * crate::TERABYTE,
+                broken: false,
+                volumes: Arc::default(),
+            },
+        }),
+    );
+
+    pool_manager.map.insert(
+        p2_dev.clone(),
+        Pool::Down(TestDownPool {
+            name: p2_label.clone(),
+            size: 1 * crate::TERABYTE,
+            up: TestUpPool {
+                name: p2_label.clone(),
+                path: Path::new("/mnt").join(&p2_label),
+                size: 1 * crate::TERABYTE,
+                broken: true,
+                volumes: Arc::default(),
+            },
+        }),
+    );
+
+    let mgr = StorageManager::new(blk, pool_manager)
+        .await
+        .expect("manager failed to create");
+
+    assert_eq!(mgr.ssds.len(), 1);
+    assert_eq!(mgr.hdds.len(), 0);
+    assert_eq!(mgr.ssd_size, 1 * crate::TERABYTE);
+
+    let broken = mgr.broken_pools();
+    assert_eq!(broken.len(), 2);
+    assert!(broken.iter().any(|(name, _)| name == "pool-2"));
+    assert!(broken.iter().any(|(name, _)| name == "/dev/test3"));
+}
+
+#[tokio::test]
+async fn manager_health() {
+    use crate::storage::device::test::*;
+    use crate::storage::device::DeviceType;
+
+    let device = |path: &str, label: &str, device_type| TestDevice {
+        path: path.into(),
+        device_type,
+        filesystem: Some("test".into()),
+        label: Some(label.into()),
+        size: 1 * crate::TERABYTE,
+    };
+
+    let pool = |label: &str, broken, volumes| {
+        Pool::Down(TestDownPool {
+            name: label.into(),
+            size: 1 * crate::TERABYTE,
+            up: TestUpPool {
+                name: label.into(),
+                path: Path::new("/mnt").join(label),
+                size: 1 * crate::TERABYTE,
+                broken,
+                volumes: Arc::new(Mutex::new(volumes)),
+            },
+        })
+    };
+
+    let blk = TestManager {
+        devices: vec![
+            device("/dev/test1", "pool-1", DeviceType::SSD),
+            device("/dev/test2", "pool-2", DeviceType::SSD),
+            device("/dev/test3", "pool-3", DeviceType::HDD),
+            device("/dev/test4", "pool-4", DeviceType::SSD),
+        ],
+    };
+
+    let mut pool_manager = TestPoolManager::default();
+    pool_manager.map.insert(
+        "/dev/test1".into(),
+        pool(
+            "pool-1",
+            false,
+            vec![TestVolume {
+                id: 0,
+                name: "workload-1".into(),
+                path: Path::new("/mnt/pool-1/workload-1").into(),
+                usage: 10 * crate::GIGABYTE,
+                ..Default::default()
+            }],
+        ),
+    );
+    pool_manager
+        .map
+        .insert("/dev/test2".into(), pool("pool-2", false, vec![]));
+    pool_manager
+        .map
+        .insert("/dev/test3".into(), pool("pool-3", false, vec![]));
+    pool_manager
+        .map
+        .insert("/dev/test4".into(), pool("pool-4", true, vec![]));
+
+    let mut mgr = StorageManager::new(blk, pool_manager)
+        .await
+        .expect("manager failed to create");
+
+    let health = mgr.health().await.unwrap();
+    assert_eq!(health.ssd.size, 2 * crate::TERABYTE);
+    assert_eq!(health.ssd.used, 10 * crate::GIGABYTE);
+    assert_eq!(health.hdd.size, 1 * crate::TERABYTE);
+    assert_eq!(health.hdd.used, 0);
+    assert_eq!(health.pools_up, 1);
+    assert_eq!(health.pools_down, 2);
+    assert_eq!(health.broken, vec!["pool-4"]);
+
+    mgr.device_allocate(crate::TERABYTE).await.unwrap();
+    let health = mgr.health().await.unwrap();
+    assert_eq!(health.hdd.used, 1 * crate::TERABYTE);
+    assert_eq!(health.pools_up, 2);
+    assert_eq!(health.pools_down, 1);
+}
+
+#[tokio::test]
+async fn manager_rescan() {
+    use crate::storage::device::test::*;
+    use crate::storage::device::DeviceType;
+
+    let device = |path: &str, label: &str, device_type| TestDevice {
+        path: path.into(),
+        device_type,
+        filesystem: Some("test".into()),
+        label: Some(label.into()),
+        size: 1 * crate::TERABYTE,
+    };
+
+    let pool = |label: &str| {
+        Pool::Down(TestDownPool {
+            name: label.into(),
+            size: 1 * crate::TERABYTE,
+            up: TestUpPool {
+                name: label.into(),
+                path: Path::new("/mnt").join(label),
+                size: 1 * crate::TERABYTE,
+                broken: false,
+                volumes: Arc::new(Mutex::new(vec![])),
+            },
+        })
+    };
+
+    let blk = TestManager {
+        devices: vec![device("/dev/test1", "pool-1", DeviceType::SSD)],
+    };
+
+    let mut pool_manager = TestPoolManager::default();
+    pool_manager.map.insert("/dev/test1".into(), pool("pool-1"));
+    pool_manager.map.insert("/dev/test2".into(), pool("pool-2"));
+    pool_manager.map.insert("/dev/test3".into(), pool("pool-3"));
+
+    let mut mgr = StorageManager::new(blk, pool_manager)
+        .await
+        .expect("manager failed to create");
+
+    assert_eq!(mgr.pools().await.unwrap().len(), 1);
+    // nothing changed
+    assert!(mgr.rescan().await.unwrap().is_empty());
+
+    // hot plug 2 devices
+    mgr.device_mgr
+        .devices
+        .push(device("/dev/test2", "pool-2", DeviceType::SSD));
+    mgr.device_mgr
+        .devices
+        .push(device("/dev/test3", "pool-3", DeviceType::HDD));
+
+    let added = mgr.rescan().await.unwrap();
+    assert_eq!(added, vec!["pool-2", "pool-3"]);
+    assert_eq!(mgr.ssds.len(), 2);
+    assert_eq!(mgr.hdds.len(), 1);
+    assert_eq!(mgr.ssd_size, 2 * crate::TERABYTE);
+    assert_eq!(mgr.hdd_size, 1 * crate::TERABYTE);
+
+    // known pools are not added again
+    assert!(mgr.rescan().await.unwrap().is_empty());
+    assert_eq!(mgr.pools().await.unwrap().len(), 3);
+
+    // a device that is gone keeps its pool
+    mgr.device_mgr.devices.remove(0);
+    assert!(mgr.rescan().await.unwrap().is_empty());
+    assert_eq!(mgr.pools().await.unwrap().len(), 3);
+}
+
+#[tokio::test]
+async fn manager_vol_migrate() {
+    use crate::storage::device::test::*;
+    use crate::storage::device::DeviceType;
+
+    let root = tempfile::tempdir().unwrap();
+    let device = |path: &str, label: &str| TestDevice {
+        path: path.into(),
+        device_type: DeviceType::SSD,
+        filesystem: Some("test".into()),
+        label: Some(label.into()),
+        size: 100 * crate::GIGABYTE,
+    };
+
+    let pool = |label: &str, volumes| {
+        Pool::Down(TestDownPool {
+            name: label.into(),
+            size: 100 * crate::GIGABYTE,
+            up: TestUpPool {
+                name: label.into(),
+                path: root.path().join(label),
+                size: 100 * crate::GIGABYTE,
+                broken: false,
+                volumes: Arc::new(Mutex::new(volumes)),
+            },
+        })
+    };
+
+    let source = root.path().join("pool-1/workload-1");
+    tokio::fs::create_dir_all(source.join("data"))
+        .await
+        .unwrap();
+    tokio::fs::write(source.join("data/file"), "content")
+        .await
+        .unwrap();
+
+    let volume = |name: &str, usage, quota| TestVolume {
+        id: 0,
+        name: name.into(),
+        path: root.path().join("pool-1").join(name),
+        usage,
+        quota,
+        ..Default::default()
+    };
+
+    let blk = TestManager {
+        devices: vec![
+            device("/dev/test1", "pool-1"),
+            device("/dev/test2", "pool-2"),
+        ],
+    };
+
+    let mut pool_manager = TestPoolManager::default();
+    pool_manager.map.insert(
+        "/dev/test1".into(),
+        pool(
+            "pool-1",
+            vec![
+                volume(
+                    "workload-1",
+                    10 * crate::GIGABYTE,
+                    Some(10 * crate::GIGABYTE),
+                ),
+                volume(
+                    "workload-2",
+                    200 * crate::GIGABYTE,
+                    Some(200 * crate::GIGABYTE),
+                ),
+                volume("workload-3", 1 * crate::GIGABYTE, None),
+            ],
+        ),
+    );
+    pool_manager
+        .map
+        .insert("/dev/test2".into(), pool("pool-2", vec![]));
+
+    let mut mgr = StorageManager::new(blk, pool_manager)
+        .await
+        .expect("manager failed to create");
+
+    let vol = mgr.volume_migrate("workload-1", "pool-2").await.unwrap();
+    assert_eq!(vol.path, root.path().join("pool-2/workload-1"));
+
+    let content = tokio::fs::read_to_string(vol.path.join("data/file"))
+        .await
+        .unwrap();
+    assert_eq!(content, "content");
+
+    // volume is only listed once, on the target pool
+    let volumes = mgr.volumes().await.unwrap();
+    let found: Vec<&VolumeInfo> = volumes.iter().filter(|v| v.name == "workload-1").collect();
+    assert_eq!(found.len(), 1);
+    assert_eq!(found[0].path, vol.path);
+
+    // size limit is kept
+    let pools = mgr.pools().await.unwrap();
+    let target = pools.iter().find(|p| p.name == "pool-2").unwrap();
+    assert_eq!(target.usage.used, 10 * crate::GIGABYTE);
+
+    // an unlimited volume is not capped to its current usage
+    tokio::fs::create_dir_all(root.path().join("pool-1/workload-3"))
+        .await
+        .unwrap();
+    mgr.volume_migrate("workload-3", "pool-2").await.unwrap();
+    let pools = mgr.pools().await.unwrap();
+    let target = pools.iter().find(|p| p.name == "pool-2").unwrap();
+    assert_eq!(target.usage.used, 10 * crate::GIGABYTE);
+
+    // target pool has no room for this one
+    let result = mgr.volume_migrate("workload-2", "pool-2").await;
+    assert!(matches!(result, Err(StorageError::NoEnoughSpaceLeft)));
+    assert!(mgr.volume_lookup("workload-2").await.is_ok());
+
+    let result = mgr.volume_migrate("workload-1", "pool-3").await;
+    assert!(matches!(result, Err(StorageError::NotFound { .. })));
+}
+
+#[tokio::test]
+async fn manager_free_space() {
+    use crate::storage::device::test::*;
+    use crate::storage::device::DeviceType;
+
+    let device = |path: &str, label: &str, device_type| TestDevice {
+        path: path.into(),
+        device_type,
+        filesystem: Some("test".into()),
+        label: Some(label.into()),
+        size: 1 * crate::TERABYTE,
+    };
+
+    let pool = |label: &str, volumes| {
+        Pool::Down(TestDownPool {
+            name: label.into(),
+            size: 1 * crate::TERABYTE,
+            up: TestUpPool {
+                name: label.into(),
+                path: Path::new("/mnt").join(label),
+                size: 1 * crate::TERABYTE,
+                broken: false,
+                volumes: Arc::new(Mutex::new(volumes)),
+            },
+        })
+    };
+
+    let blk = TestManager {
+        devices: vec![
+            device("/dev/test1", "pool-1", DeviceType::SSD),
+            device("/dev/test2", "pool-2", DeviceType::SSD),
+            device("/dev/test3", "pool-3", DeviceType::HDD),
+            device("/dev/test4", "pool-4", DeviceType::HDD),
+        ],
+    };
+
+    let mut pool_manager = TestPoolManager::default();
+    pool_manager.map.insert(
+        "/dev/test1".into(),
+        pool(
+            "pool-1",
+            vec![TestVolume {
+                id: 0,
+                name: "workload-1".into(),
+                path: Path::new("/mnt/pool-1/workload-1").into(),
+                usage: 100 * crate::GIGABYTE,
+                ..Default::default()
+            }],
+        ),
+    );
+    pool_manager
+        .map
+        .insert("/dev/test2".into(), pool("pool-2", vec![]));
+    pool_manager
+        .map
+        .insert("/dev/test3".into(), pool("pool-3", vec![]));
+    pool_manager
+        .map
+        .insert("/dev/test4".into(), pool("pool-4", vec![]));
+
+    let mut mgr = StorageManager::new(blk, pool_manager)
+        .await
+        .expect("manager failed to create");
+
+    assert_eq!(mgr.total_space(DeviceType::SSD), 2 * crate::TERABYTE);
+    assert_eq!(mgr.total_space(DeviceType::HDD), 2 * crate::TERABYTE);
+
+    // pool-1 is up with 100G used, pool-2 is down so all free
+    assert_eq!(
+        mgr.free_space(DeviceType::SSD).await.unwrap(),
+        2 * crate::TERABYTE - 100 * crate::GIGABYTE
+    );
+    assert_eq!(
+        mgr.free_space(DeviceType::HDD).await.unwrap(),
+        2 * crate::TERABYTE
+    );
+
+    // allocated hdd is not free anymore
+    mgr.device_allocate(crate::TERABYTE).await.unwrap();
+    assert_eq!(
+        mgr.free_space(DeviceType::HDD).await.unwrap(),
+        1 * crate::TERABYTE
+    );
+    assert_eq!(mgr.total_space(DeviceType::HDD), 2 * crate::TERABYTE);
+}
+
+#[tokio::test]
+async fn manager_pools() {
+    use crate::storage::device::test::*;
+    use crate::storage::device::DeviceType;
+
+    let device = |path: &str, label: &str, device_type, size| TestDevice {
+        path: path.into(),
+        device_type,
+        filesystem: Some("test".into()),
+        label: Some(label.into()),
+        size,
+    };
+
+    let pool = |label: &str, size, volumes| {
+        Pool::Down(TestDownPool {
+            name: label.into(),
+            size,
+            up: TestUpPool {
+                name: label.into(),
+                path: Path::new("/mnt").join(label),
+                size,
+                broken: false,
+                volumes: Arc::new(Mutex::new(volumes)),
+            },
+        })
+    };
+
+    let blk = TestManager {
+        devices: vec![
+            device("/dev/test1", "pool-1", DeviceType::SSD, 1 * crate::TERABYTE),
+            device(
+                "/dev/test2",
+                "pool-2",
+                DeviceType::SSD,
+                500 * crate::GIGABYTE,
+            ),
+            device("/dev/test3", "pool-3", DeviceType::HDD, 4 * crate::TERABYTE),
+        ],
+    };
+
+    let mut pool_manager = TestPoolManager::default();
+    pool_manager.map.insert(
+        "/dev/test1".into(),
+        pool(
+            "pool-1",
+            1 * crate::TERABYTE,
+            vec![TestVolume {
+                id: 0,
+                name: "workload-1".into(),
+                path: Path::new("/mnt/pool-1/workload-1").into(),
+                usage: 10 * crate::GIGABYTE,
+                ..Default::default()
+            }],
+        ),
+    );
+    pool_manager.map.insert(
+        "/dev/test2".into(),
+        pool("pool-2", 500 * crate::GIGABYTE, vec![]),
+    );
+    pool_manager.map.insert(
+        "/dev/test3".into(),
+        pool("pool-3", 4 * crate::TERABYTE, vec![]),
+    );
+
+    let mgr = StorageManager::new(blk, pool_manager)
+        .await
+        .expect("manager failed to create");
+
+    let mut pools = mgr.pools().await.unwrap();
+    pools.sort_by(|a, b| a.name.cmp(&b.name));
+    assert_eq!(pools.len(), 3);
+
+    assert_eq!(pools[0].name, "pool-1");
+    assert_eq!(pools[0].state, State::Up);
+    assert_eq!(pools[0].device_type, DeviceType::SSD);
+    assert_eq!(pools[0].usage.size, 1 * crate::TERABYTE);
+    assert_eq!(pools[0].usage.used, 10 * crate::GIGABYTE);
+
+    assert_eq!(pools[1].name, "pool-2");
+    assert_eq!(pools[1].state, State::Down);
+    assert_eq!(pools[1].device_type, DeviceType::SSD);
+    assert_eq!(pools[1].usage.size, 500 * crate::GIGABYTE);
+    assert_eq!(pools[1].usage.used, 0);
+
+    assert_eq!(pools[2].name, "pool-3");
+    assert_eq!(pools[2].state, State::Down);
+    assert_eq!(pools[2].device_type, DeviceType::HDD);
+    assert_eq!(pools[2].usage.size, 4 * crate::TERABYTE);
+}
+
+// verify_pools returns a pool manager with one ssd pool that has the
+// given volumes, and the device manager that holds its device
+fn verify_pools(
+    label: &str,
+    volumes: Vec<TestVolume>,
+) -> (crate::storage::device::test::TestManager, TestPoolManager) {
+    use crate::storage::device::test::*;
+    use crate::storage::device::DeviceType;
+
+    let dev: PathBuf = "/dev/test1".into();
+    let pool_path = Path::new("/mnt").join(label);
+
+    let blk = TestManager {
+        devices: vec![TestDevice {
+            path: dev.clone(),
+            device_type: DeviceType::SSD,
+            filesystem: Some("test".into()),
+            label: Some(label.into()),
+            size: 1 * crate::TERABYTE,
+        }],
+    };
+
+    let mut pool_manager = TestPoolManager::default();
+    pool_manager.map.insert(
+        dev,
+        Pool::Down(TestDownPool {
+            name: label.into(),
+            size: 1 * crate::TERABYTE,
+            up: TestUpPool {
+                name: label.into(),
+                path: pool_path,
+                size: 1 * crate::TERABYTE,
+                broken: false,
+                volumes: Arc::new(Mutex::new(volumes)),
+            },
+        }),
+    );
+
+    (blk, pool_manager)
+}
+
+#[tokio::test]
+async fn manager_verify() {
+    // the vdisks volume is gone from the pool volumes list
+    let (blk, pool_manager) = verify_pools(
+        "pool-verify",
+        vec![TestVolume {
+            id: 1,
+            name: "workload-1".into(),
+            path: Path::new("/mnt/pool-verify/workload-1").into(),
+            ..Default::default()
+        }],
+    );
+
+    let mgr = StorageManager::new(blk, pool_manager)
+        .await
+        .expect("manager failed to create");
+
+    let missing = mgr.verify(false).await.unwrap();
+    assert_eq!(missing, vec!["pool-verify"]);
+    assert!(mgr.volume_lookup("vdisks").await.is_err());
+
+    let missing = mgr.verify(true).await.unwrap();
+    assert_eq!(missing, vec!["pool-verify"]);
+
+    // recreated as a volume of the pool
+    let vol = mgr.volume_lookup("vdisks").await.unwrap();
+    assert_eq!(vol.path, Path::new("/mnt/pool-verify/vdisks"));
+
+    let missing = mgr.verify(false).await.unwrap();
+    assert!(missing.is_empty());
+}
+
+#[tokio::test]
+async fn manager_verify_startup() {
+    // pools without volumes are brought down, so keep one volume around
+    let (blk, pool_manager) = verify_pools(
+        "pool-verify-startup",
+        vec![TestVolume {
+            id: 1,
+            name: "workload-1".into(),
+            path: Path::new("/mnt/pool-verify-startup/workload-1").into(),
+            ..Default::default()
+        }],
+    );
+
+    let options = Options {
+        verify: true,
+        ..Default::default()
+    };
+    let mgr = StorageManager::with_options(blk, pool_manager, options)
+        .await
+        .expect("manager failed to create");
+
+    // missing volume was recreated during startup
+    assert!(mgr.volume_lookup("vdisks").await.is_ok());
+    assert!(mgr.verify(false).await.unwrap().is_empty());
+}
+
+async fn spindown_manager(
+    spindown: bool,
+    hdparm: usize,
+) -> StorageManager<
+    crate::storage::device::LsBlk<crate::system::MockExecutor>,
+    TestPoolManager,
+    TestUpPool,
+    TestDownPool,
+> {
+    use crate::storage::device::LsBlk;
+    use crate::system::{Command, MockExecutor};
+
+    const LSBLK_LIST: &str = r#"{
+        "blockdevices": [
+           {"path":"/dev/sdb", "name":"/dev/sdb", "size":3000592982016, "subsystems":"block:scsi:pci", "fstype":"btrfs", "label":"pool-1", "rota":true}
+        ]
+     }"#;
+
+    let mut exec = MockExecutor::default();
+    let list = Command::new("lsblk")
+        .arg("--json")
+        .arg("-o")
+        .arg("PATH,NAME,SIZE,SUBSYSTEMS,FSTYPE,LABEL,ROTA")
+        .arg("--bytes")
+        .arg("--exclude")
+        .arg("1,2,11");
+    let seektime = Command::new("seektime").arg("-j").arg("/dev/sdb");
+    let shutdown = Command::new("hdparm").arg("-y").arg("/dev/sdb");
+
+    exec.expect_run()
+        .withf(move |arg: &Command| arg == &list)
+        .returning(|_| Ok(Vec::from(LSBLK_LIST)));
+
+    exec.expect_run()
+        .withf(move |arg: &Command| arg == &seektime)
+        .returning(|_| Ok(Vec::from(r#"{"type": "HDD", "elapsed": 10000}"#)));
+
+    exec.expect_run()
+        .withf(move |arg: &Command| arg == &shutdown)
+        .times(hdparm)
+        .returning(|_| Ok(Vec::default()));
+
+    let mut pool_manager = TestPoolManager::default();
+    pool_manager.map.insert(
+        "/dev/sdb".into(),
+        Pool::Down(TestDownPool {
+            name: "pool-1".into(),
+            size: 3 * crate::TERABYTE,
+            up: TestUpPool {
+                name: "pool-1".into(),
+                path: Path::new("/mnt").join("pool-1"),
+                size: 3 * crate::TERABYTE,
+                broken: false,
+                volumes: Arc::default(),
+            },
+        }),
+    );
+
+    StorageManager::with_spindown(LsBlk::new(exec), pool_manager, spindown)
+        .await
+        .expect("manager failed to create")
+}
+
+#[tokio::test]
+async fn manager_spindown() {
+    // the hdd pool has no volumes so it's brought down and powered off
+    let mgr = spindown_manager(true, 1).await;
+    assert_eq!(mgr.hdds.len(), 1);
+    assert_eq!(mgr.hdds[0].state(), State::Down);
+}
+
+#[tokio::test]
+async fn manager_spindown_disabled() {
+    let mgr = spindown_manager(false, 0).await;
+    assert_eq!(mgr.hdds.len(), 1);
+    assert_eq!(mgr.hdds[0].state(), State::Down);
+}
+
+// records bind mounts as (source, target)
+#[derive(Default)]
+struct TestSyscalls {
+    pub mounts: std::sync::Mutex<Vec<(PathBuf, PathBuf)>>,
+}
+
+impl crate::system::Syscalls for TestSyscalls {
+    fn mount<S: AsRef<Path>, T: AsRef<Path>, F: AsRef<str>, D: AsRef<str>>(
+        &self,
+        source: Option<S>,
+        target: T,
+        _fstype: Option<F>,
+        _flags: nix::mount::MsFlags,
+        _data: Option<D>,
+    ) -> std::result::Result<(), crate::system::Error> {
+        let source = source.map(|s| s.as_ref().into()).unwrap_or_default();
+        self.mounts
+            .lock()
+            .unwrap()
+            .push((source, target.as_ref().into()));
+        Ok(())
+    }
+
+    fn umount<T: AsRef<Path>>(
+        &self,
+        _target: T,
+        _flags: Option<nix::mount::MntFlags>,
+    ) -> std::result::Result<(), crate::system::Error> {
+        Ok(())
+    }
+}
+
+#[tokio::test]
+async fn manager_ensure_cache() {
+    use crate::storage::device::test::*;
+    use crate::storage::device::DeviceType;
+
+    let p1_dev: PathBuf = "/dev/test1".into();
+    let p1_label: String = "pool-1".into();
+
+    let blk = TestManager {
+        devices: vec![TestDevice {
+            path: p1_dev.clone(),
+            device_type: DeviceType::SSD,
+            filesystem: Some("test".into()),
+            label: Some(p1_label.clone()),
+            size: 1 * crate::TERABYTE,
+        }],
+    };
+
+    let mut pool_manager = TestPoolManager::default();
+    pool_manager.map.insert(
+        p1_dev.clone(),
         Pool::Down(TestDownPool {
-            name: p3_label.clone(),
-            size: 4 * crate::TERABYTE,
+            name: p1_label.clone(),
+            size: 1 * crate::TERABYTE,
             up: TestUpPool {
-                name: p3_label.clone(),
-                path: Path::new("/mnt").join(p3_label),
-                size: 4 * crate::TERABYTE,
+                name: p1_label.clone(),
+                path: Path::new("/mnt").join(p1_label),
+                size: 1 * crate::TERABYTE,
+                broken: false,
                 volumes: Arc::default(),
             },
         }),
     );
 
-    let mgr = StorageManager::new(blk, pool_manager)
+    let mut mgr = StorageManager::new(blk, pool_manager)
         .await
         .expect("manager failed to create");
 
-    assert_eq!(mgr.ssds.len(), 2);
-    assert_eq!(mgr.hdds.len(), 1);
-    assert_eq!(mgr.ssd_size, 2 * crate::TERABYTE);
-    assert_eq!(mgr.hdd_size, 4 * crate::TERABYTE);
-
-    let pool_1 = &mgr.ssds.iter().find(|p| p.name() == "pool-1").unwrap();
-    assert_eq!(pool_1.state(), State::Down);
-
-    let pool_2 = &mgr.ssds.iter().find(|p| p.name() == "pool-2").unwrap();
-    assert_eq!(pool_2.state(), State::Up);
+    let dir = tempfile::tempdir().unwrap();
+    let target = dir.path().join("cache");
+    let sys = TestSyscalls::default();
 
-    let volumes = mgr.volumes().await.unwrap();
-    assert_eq!(volumes.len(), 1);
+    let path = mgr
+        .mount_cache(&sys, &target)
+        .await
+        .expect("failed to ensure cache");
 
-    let cache_vol = &volumes[0];
-    assert_eq!(cache_vol.name, "zos-cache");
-    assert_eq!(cache_vol.path, Path::new("/mnt/pool-2/zos-cache"));
+    assert_eq!(path, target);
 
-    // find volume by name.
     let vol = mgr.volume_lookup("zos-cache").await.unwrap();
-    assert_eq!(vol.name, "zos-cache");
-    assert_eq!(vol.path, Path::new("/mnt/pool-2/zos-cache"));
+    assert_eq!(vol.path, Path::new("/mnt/pool-1/zos-cache"));
 
-    let errored = mgr.volume_lookup("not-found").await;
+    let pool_1 = &mgr.ssds.iter().find(|p| p.name() == "pool-1").unwrap();
+    assert_eq!(pool_1.state(), State::Up);
 
-    assert!(matches!(errored, Err(StorageError::NotFound { kind, .. }) if kind == Kind::Volume));
+    let mounts = sys.mounts.lock().unwrap();
+    assert_eq!(mounts.len(), 1);
+    assert_eq!(mounts[0].0, Path::new("/mnt/pool-1/zos-cache"));
+    assert_eq!(mounts[0].1, target);
 }
 
 #[tokio::test]
@@ -350,6 +1205,7 @@ async fn manager_vol_create_space_available() {
                 name: p1_label.clone(),
                 path: Path::new("/mnt").join(p1_label),
                 size: 1 * crate::TERABYTE,
+                broken: false,
                 volumes: Arc::default(),
             },
         }),
@@ -364,11 +1220,13 @@ async fn manager_vol_create_space_available() {
                 name: p2_label.clone(),
                 path: Path::new("/mnt").join(&p2_label),
                 size: 1 * crate::TERABYTE,
+                broken: false,
                 volumes: Arc::new(Mutex::new(vec![TestVolume {
                     id: 0,
                     name: "zos-cache".into(),
                     path: Path::new("/mnt").join(p2_label).join("zos-cache"),
                     usage: 100 * crate::GIGABYTE,
+                    ..Default::default()
                 }])),
             },
         }),
@@ -389,6 +1247,227 @@ async fn manager_vol_create_space_available() {
     assert_eq!(vol.path, Path::new("/mnt/pool-2/vdisks"));
 }
 
+#[tokio::test]
+async fn manager_vol_rename() {
+    use crate::storage::device::test::*;
+    use crate::storage::device::DeviceType;
+
+    let p1_dev: PathBuf = "/dev/test1".into();
+    let p1_label: String = "pool-1".into();
+
+    let blk = TestManager {
+        devices: vec![TestDevice {
+            path: p1_dev.clone(),
+            device_type: DeviceType::SSD,
+            filesystem: Some("test".into()),
+            label: Some(p1_label.clone()),
+            size: 1 * crate::TERABYTE,
+        }],
+    };
+
+    let mut pool_manager = TestPoolManager::default();
+    pool_manager.map.insert(
+        p1_dev.clone(),
+        Pool::Down(TestDownPool {
+            name: p1_label.clone(),
+            size: 1 * crate::TERABYTE,
+            up: TestUpPool {
+                name: p1_label.clone(),
+                path: Path::new("/mnt").join(&p1_label),
+                size: 1 * crate::TERABYTE,
+                broken: false,
+                volumes: Arc::new(Mutex::new(vec![
+                    TestVolume {
+                        id: 0,
+                        name: "workload-1".into(),
+                        path: Path::new("/mnt").join(&p1_label).join("workload-1"),
+                        usage: 10 * crate::GIGABYTE,
+                        ..Default::default()
+                    },
+                    TestVolume {
+                        id: 1,
+                        name: "workload-2".into(),
+                        path: Path::new("/mnt").join(&p1_label).join("workload-2"),
+                        usage: 10 * crate::GIGABYTE,
+                        ..Default::default()
+                    },
+                ])),
+            },
+        }),
+    );
+
+    let mgr = StorageManager::new(blk, pool_manager)
+        .await
+        .expect("manager failed to create");
+
+    mgr.volume_rename("workload-1", "workload-3").await.unwrap();
+
+    let vol = mgr.volume_lookup("workload-3").await.unwrap();
+    assert_eq!(vol.path, Path::new("/mnt/pool-1/workload-3"));
+
+    let old = mgr.volume_lookup("workload-1").await;
+    assert!(matches!(old, Err(StorageError::NotFound { kind, .. }) if kind == Kind::Volume));
+
+    let result = mgr.volume_rename("workload-3", "workload-2").await;
+    assert!(matches!(
+        result,
+        Err(StorageError::Pool(Error::VolumeAlreadyExists { volume })) if volume == "workload-2"
+    ));
+
+    let result = mgr.volume_rename("not-found", "workload-4").await;
+    assert!(matches!(result, Err(StorageError::NotFound { kind, .. }) if kind == Kind::Volume));
+}
+
+#[tokio::test]
+async fn manager_vol_delete_force() {
+    use crate::storage::device::test::*;
+    use crate::storage::device::DeviceType;
+
+    let p1_dev: PathBuf = "/dev/test1".into();
+    let p1_label: String = "pool-delete".into();
+    let pool_path = Path::new("/tmp").join(&p1_label);
+
+    let blk = TestManager {
+        devices: vec![TestDevice {
+            path: p1_dev.clone(),
+            device_type: DeviceType::SSD,
+            filesystem: Some("test".into()),
+            label: Some(p1_label.clone()),
+            size: 1 * crate::TERABYTE,
+        }],
+    };
+
+    let mut pool_manager = TestPoolManager::default();
+    pool_manager.map.insert(
+        p1_dev.clone(),
+        Pool::Down(TestDownPool {
+            name: p1_label.clone(),
+            size: 1 * crate::TERABYTE,
+            up: TestUpPool {
+                name: p1_label.clone(),
+                path: pool_path.clone(),
+                size: 1 * crate::TERABYTE,
+                broken: false,
+                volumes: Arc::new(Mutex::new(vec![
+                    TestVolume {
+                        id: 0,
+                        name: "empty".into(),
+                        path: pool_path.join("empty"),
+                        usage: 10 * crate::GIGABYTE,
+                        ..Default::default()
+                    },
+                    TestVolume {
+                        id: 1,
+                        name: "vdisks".into(),
+                        path: pool_path.join("vdisks"),
+                        usage: 10 * crate::GIGABYTE,
+                        ..Default::default()
+                    },
+                ])),
+            },
+        }),
+    );
+
+    let _ = tokio::fs::remove_dir_all(&pool_path).await;
+    tokio::fs::create_dir_all(pool_path.join("empty"))
+        .await
+        .unwrap();
+    tokio::fs::create_dir_all(pool_path.join("vdisks"))
+        .await
+        .unwrap();
+    tokio::fs::write(pool_path.join("vdisks").join("disk-1"), "data")
+        .await
+        .unwrap();
+
+    let mgr = StorageManager::new(blk, pool_manager)
+        .await
+        .expect("manager failed to create");
+
+    let result = mgr.volume_delete_force("vdisks", false).await;
+    assert!(matches!(result, Err(StorageError::NotEmpty { volume }) if volume == "vdisks"));
+    assert!(mgr.volume_lookup("vdisks").await.is_ok());
+
+    mgr.volume_delete_force("empty", false).await.unwrap();
+    assert!(mgr.volume_lookup("empty").await.is_err());
+
+    // not found volumes are ignored
+    mgr.volume_delete_force("not-found", false).await.unwrap();
+
+    mgr.volume_delete_force("vdisks", true).await.unwrap();
+    assert!(mgr.volume_lookup("vdisks").await.is_err());
+
+    let _ = tokio::fs::remove_dir_all(&pool_path).await;
+}
+
+#[tokio::test]
+async fn manager_vol_create_concurrent() {
+    // allocation needs exclusive access to the manager, so concurrent callers
+    // have to share it behind a lock and can't both see the same free space
+    use crate::storage::device::test::*;
+    use crate::storage::device::DeviceType;
+
+    let p1_dev: PathBuf = "/dev/test1".into();
+    let p1_label: String = "pool-1".into();
+
+    let blk = TestManager {
+        devices: vec![TestDevice {
+            path: p1_dev.clone(),
+            device_type: DeviceType::SSD,
+            filesystem: Some("test".into()),
+            label: Some(p1_label.clone()),
+            size: 1 * crate::TERABYTE,
+        }],
+    };
+
+    let mut pool_manager = TestPoolManager::default();
+    pool_manager.map.insert(
+        p1_dev.clone(),
+        Pool::Down(TestDownPool {
+            name: p1_label.clone(),
+            size: 1 * crate::TERABYTE,
+            up: TestUpPool {
+                name: p1_label.clone(),
+                path: Path::new("/mnt").join(&p1_label),
+                size: 1 * crate::TERABYTE,
+                broken: false,
+                volumes: Arc::new(Mutex::new(vec![])),
+            },
+        }),
+    );
+
+    let mgr = StorageManager::new(blk, pool_manager)
+        .await
+        .expect("manager failed to create");
+    let mgr = Arc::new(Mutex::new(mgr));
+
+    let handles: Vec<_> = ["vol-1", "vol-2"]
+        .into_iter()
+        .map(|name| {
+            let mgr = Arc::clone(&mgr);
+            tokio::spawn(async move {
+                mgr.lock()
+                    .await
+                    .volume_create(name, 600 * crate::GIGABYTE)
+                    .await
+            })
+        })
+        .collect();
+
+    let mut results = vec![];
+    for handle in handles {
+        results.push(handle.await.unwrap());
+    }
+
+    assert_eq!(results.iter().filter(|r| r.is_ok()).count(), 1);
+    assert_eq!(
+        results
+            .iter()
+            .filter(|r| matches!(r, Err(StorageError::NoEnoughSpaceLeft)))
+            .count(),
+        1
+    );
+}
+
 #[tokio::test]
 async fn manager_vol_create_space_unavailable() {
     // there are 2 pools, one of them is up (because the pool has volumes)
@@ -433,6 +1512,7 @@ async fn manager_vol_create_space_unavailable() {
                 name: p1_label.clone(),
                 path: Path::new("/mnt").join(&p1_label),
                 size: 1 * crate::TERABYTE,
+                broken: false,
                 volumes: Arc::default(),
             },
         }),
@@ -447,11 +1527,13 @@ async fn manager_vol_create_space_unavailable() {
                 name: p2_label.clone(),
                 path: Path::new("/mnt").join(&p2_label),
                 size: 1 * crate::TERABYTE,
+                broken: false,
                 volumes: Arc::new(Mutex::new(vec![TestVolume {
                     id: 0,
                     name: "zos-cache".into(),
                     path: Path::new("/mnt").join(&p2_label).join("zos-cache"),
                     usage: 1 * crate::TERABYTE,
+                    ..Default::default()
                 }])),
             },
         }),
@@ -540,11 +1622,13 @@ async fn manager_vol_delete() {
                 name: p1_label.clone(),
                 path: Path::new("/mnt").join(p1_label.clone()),
                 size: 1 * crate::TERABYTE,
+                broken: false,
                 volumes: Arc::new(Mutex::new(vec![TestVolume {
                     id: 0,
                     name: "zos-cache".into(),
                     path: Path::new("/mnt").join(p1_label).join("zos-cache"),
                     usage: 100 * crate::GIGABYTE,
+                    ..Default::default()
                 }])),
             },
         }),
@@ -571,13 +1655,170 @@ async fn manager_vol_delete() {
 #[tokio::test]
 async fn mkdisk() {
     let path = Path::new("/tmp/disk");
-    let result = super::mkdisk(path, 500 * crate::MEGABYTE).await;
+    let result = super::mkdisk(path, 500 * crate::MEGABYTE, DiskMode::Preallocated).await;
+    assert!(result.is_ok());
+    let meta = tokio::fs::metadata(path).await.unwrap();
+    assert_eq!(meta.len(), 500 * crate::MEGABYTE);
+    let _ = tokio::fs::remove_file(path).await;
+}
+
+#[tokio::test]
+async fn mkdisk_sparse() {
+    use std::os::unix::fs::MetadataExt;
+
+    let path = Path::new("/tmp/disk-sparse");
+    let result = super::mkdisk(path, 500 * crate::MEGABYTE, DiskMode::Sparse).await;
     assert!(result.is_ok());
     let meta = tokio::fs::metadata(path).await.unwrap();
     assert_eq!(meta.len(), 500 * crate::MEGABYTE);
+    // no blocks are allocated for sparse disks
+    assert!(meta.blocks() * 512 < crate::MEGABYTE);
+    let _ = tokio::fs::remove_file(path).await;
+}
+
+#[tokio::test]
+async fn mkdisk_nocow() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("disk-nocow");
+    let nocow = super::mkdisk(&path, 10 * crate::MEGABYTE, DiskMode::Sparse)
+        .await
+        .expect("disk creation must not fail if NOCOW is not supported");
+
+    // disk info reads back the same flag
+    let meta = tokio::fs::metadata(&path).await.unwrap();
+    assert_eq!(super::disk_info(path.clone(), &meta).nocow, nocow);
+
+    if !nocow {
+        // filesystem does not support NOCOW (for example tmpfs)
+        return;
+    }
+
+    // NOCOW can't be set on a file that already has data, but it's
+    // already set here so this is a no-op
+    let file = std::fs::File::open(&path).unwrap();
+    assert_eq!(super::set_nocow(&file), Ok(true));
+}
+
+#[test]
+fn copy_file_sparse() {
+    use std::io::{Seek, SeekFrom, Write};
+    use std::os::unix::fs::MetadataExt;
+
+    let dir = tempfile::tempdir().unwrap();
+    let source = dir.path().join("source");
+    let target = dir.path().join("target");
+
+    let mut file = std::fs::File::create(&source).unwrap();
+    let nocow = matches!(super::set_nocow(&file), Ok(true));
+    file.set_len(10 * crate::MEGABYTE).unwrap();
+    file.seek(SeekFrom::Start(4 * crate::MEGABYTE)).unwrap();
+    file.write_all(b"disk data").unwrap();
+    drop(file);
+
+    super::copy_file(&source, &target).unwrap();
+
+    let source_meta = std::fs::metadata(&source).unwrap();
+    let meta = std::fs::metadata(&target).unwrap();
+    assert_eq!(meta.len(), 10 * crate::MEGABYTE);
+    assert_eq!(meta.mode(), source_meta.mode());
+    // holes are not written out
+    assert!(meta.blocks() * 512 < crate::MEGABYTE);
+
+    let content = std::fs::read(&target).unwrap();
+    let at = 4 * crate::MEGABYTE as usize;
+    assert_eq!(&content[at..at + 9], b"disk data");
+    assert!(content[..at].iter().all(|b| *b == 0));
+
+    if nocow {
+        let file = std::fs::File::open(&target).unwrap();
+        let flags = super::get_flags(&file).unwrap();
+        assert_ne!(flags & super::FS_NOCOW_FL, 0);
+    }
+}
+
+#[tokio::test]
+async fn expand_disk() {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let path = Path::new("/tmp/disk-expand");
+    let _ = tokio::fs::remove_file(path).await;
+    super::mkdisk(path, 25 * crate::MEGABYTE, DiskMode::Preallocated)
+        .await
+        .unwrap();
+
+    let mut file = tokio::fs::OpenOptions::new()
+        .write(true)
+        .open(path)
+        .await
+        .unwrap();
+    file.write_all(b"disk header").await.unwrap();
+    file.sync_all().await.unwrap();
+    drop(file);
+
+    super::expand_disk(path, 50 * crate::MEGABYTE, DiskMode::Preallocated)
+        .await
+        .unwrap();
+    let meta = tokio::fs::metadata(path).await.unwrap();
+    assert_eq!(meta.len(), 50 * crate::MEGABYTE);
+
+    let mut header = [0; 11];
+    let mut file = tokio::fs::File::open(path).await.unwrap();
+    file.read_exact(&mut header).await.unwrap();
+    assert_eq!(&header, b"disk header");
+
+    // shrinking is not allowed
+    let result = super::expand_disk(path, 25 * crate::MEGABYTE, DiskMode::Sparse).await;
+    assert!(matches!(result, Err(StorageError::InvalidSize { .. })));
+    let _ = tokio::fs::remove_file(path).await;
+}
+
+#[tokio::test]
+async fn disk_detect_fs() {
+    use tokio::io::{AsyncSeekExt, AsyncWriteExt};
+
+    let path = Path::new("/tmp/disk-detect");
+    let _ = tokio::fs::remove_file(path).await;
+    super::mkdisk(path, 25 * crate::MEGABYTE, DiskMode::Sparse)
+        .await
+        .unwrap();
+
+    // a new disk is all zeros
+    assert_eq!(super::detect_fs(path).await.unwrap(), None);
+
+    // ext4 magic in the super block at 1024 + 0x38
+    let mut file = tokio::fs::OpenOptions::new()
+        .write(true)
+        .open(path)
+        .await
+        .unwrap();
+    file.seek(std::io::SeekFrom::Start(0x438)).await.unwrap();
+    file.write_all(&[0x53, 0xef]).await.unwrap();
+    file.sync_all().await.unwrap();
+    drop(file);
+
+    assert_eq!(super::detect_fs(path).await.unwrap(), Some("ext4"));
     let _ = tokio::fs::remove_file(path).await;
 }
 
+#[test]
+fn disk_detect_signature() {
+    let mut header = vec![0; super::DISK_HEADER_SIZE as usize];
+    assert_eq!(super::detect_signature(&header), None);
+    // too short to hold any super block
+    assert_eq!(super::detect_signature(&header[..100]), None);
+
+    header[0x1fe..0x200].copy_from_slice(&[0x55, 0xaa]);
+    assert_eq!(super::detect_signature(&header), None);
+    header[0x52..0x57].copy_from_slice(b"FAT32");
+    assert_eq!(super::detect_signature(&header), Some("vfat"));
+
+    header[0x200..0x208].copy_from_slice(b"EFI PART");
+    assert_eq!(super::detect_signature(&header), Some("gpt"));
+
+    header[0x10040..0x10048].copy_from_slice(b"_BHRfS_M");
+    assert_eq!(super::detect_signature(&header), Some("btrfs"));
+}
+
 #[tokio::test]
 async fn manager_disk() {
     use crate::storage::device::test::*;
@@ -609,6 +1850,7 @@ async fn manager_disk() {
                 name: p1_label.clone(),
                 path: pool_path.clone(),
                 size: 1 * crate::TERABYTE,
+                broken: false,
                 volumes: Arc::new(Mutex::new(vec![])),
             },
         }),
@@ -633,7 +1875,7 @@ async fn manager_disk() {
     assert_eq!(disks.len(), 0);
 
     let disk = mgr
-        .disk_create("test.50", 50 * crate::MEGABYTE)
+        .disk_create("test.50", 50 * crate::MEGABYTE, DiskMode::Preallocated)
         .await
         .unwrap();
 
@@ -644,11 +1886,13 @@ async fn manager_disk() {
     assert_eq!(vol.path, Path::new("/tmp/pool-1/vdisks"));
 
     let disk = mgr
-        .disk_create("test.25", 25 * crate::MEGABYTE)
+        .disk_create("test.25", 25 * crate::MEGABYTE, DiskMode::Preallocated)
         .await
         .unwrap();
     assert_eq!(disk.path, Path::new("/tmp/pool-1/vdisks/test.25"));
     assert_eq!(disk.size, 25 * crate::MEGABYTE);
+    // disk space is allocated on creation
+    assert!(disk.used >= 25 * crate::MEGABYTE);
 
     let disks = mgr.disks().await.unwrap();
     assert_eq!(disks.len(), 2);
@@ -665,6 +1909,24 @@ async fn manager_disk() {
 
     assert_eq!(disk.path, Path::new("/tmp/pool-1/vdisks/test.50"));
     assert_eq!(disk.size, 50 * crate::MEGABYTE);
+    assert!(disk.used >= 50 * crate::MEGABYTE);
+
+    // sparse disk files report less used space than their size
+    let disk = mgr
+        .disk_create("sparse", 10 * crate::MEGABYTE, DiskMode::Sparse)
+        .await
+        .unwrap();
+    assert_eq!(disk.size, 10 * crate::MEGABYTE);
+    assert!(disk.used < crate::MEGABYTE);
+
+    // and stay sparse when expanded
+    mgr.disk_expand("sparse", 20 * crate::MEGABYTE)
+        .await
+        .unwrap();
+    let disk = mgr.disk_lookup("sparse").await.unwrap();
+    assert_eq!(disk.size, 20 * crate::MEGABYTE);
+    assert!(disk.used < crate::MEGABYTE);
+    mgr.disk_delete("sparse").await.unwrap();
 
     mgr.disk_delete("test.50").await.unwrap();
 
@@ -680,6 +1942,46 @@ async fn manager_disk() {
 
     let disk = mgr.disk_lookup("test.25").await.unwrap();
     assert_eq!(disk.size, 50 * crate::MEGABYTE);
+
+    let disk = mgr.disk_lookup("../vdisks/test.25").await;
+    assert!(matches!(
+        disk,
+        Err(crate::storage::Error::InvalidName { .. })
+    ));
+
+    let disk = mgr
+        .disk_create("../escaped", 25 * crate::MEGABYTE, DiskMode::Preallocated)
+        .await;
+    assert!(matches!(
+        disk,
+        Err(crate::storage::Error::InvalidName { .. })
+    ));
+    assert!(!pool_path.join("escaped").exists());
+
+    let result = mgr.disk_delete("../../etc/shadow").await;
+    assert!(matches!(
+        result,
+        Err(crate::storage::Error::InvalidName { .. })
+    ));
+}
+
+#[test]
+fn validate_disk_name() {
+    use super::validate_disk_name;
+
+    assert!(validate_disk_name("test.25").is_ok());
+    assert!(validate_disk_name("vm-disk_1").is_ok());
+
+    for name in ["", "..", "../name", "a/b", "/etc/shadow", "name\0"] {
+        assert!(
+            matches!(
+                validate_disk_name(name),
+                Err(crate::storage::Error::InvalidName { .. })
+            ),
+            "name '{}' must be rejected",
+            name
+        );
+    }
 }
 
 #[tokio::test]
@@ -726,11 +2028,13 @@ async fn manager_device_allocate() {
                 name: p1_label.clone(),
                 path: pool1_path.clone(),
                 size: 1 * crate::TERABYTE,
+                broken: false,
                 volumes: Arc::new(Mutex::new(vec![TestVolume {
                     id: 0,
                     name: "zdb".into(),
                     path: pool1_path.join("zdb"),
                     usage: 100 * crate::MEGABYTE,
+                    ..Default::default()
                 }])),
             },
         }),
@@ -745,6 +2049,7 @@ async fn manager_device_allocate() {
                 name: p2_label.clone(),
                 path: pool2_path.clone(),
                 size: 1 * crate::TERABYTE,
+                broken: false,
                 volumes: Arc::new(Mutex::new(Vec::default())),
             },
         }),
@@ -787,5 +2092,23 @@ async fn manager_device_allocate() {
     let device = mgr.device_lookup("does-not-exist").await;
     assert!(
         matches!(device, Err(crate::storage::Error::NotFound{kind, ..}) if kind == Kind::Device )
-    )
+    );
+
+    mgr.device_release(&p2_label).await.unwrap();
+    assert_eq!(
+        mgr.hdds
+            .iter()
+            .find(|p| p.name() == p2_label)
+            .unwrap()
+            .state(),
+        State::Down
+    );
+
+    let devices = mgr.devices().await.unwrap();
+    assert_eq!(devices.len(), 1);
+
+    let result = mgr.device_release("does-not-exist").await;
+    assert!(
+        matches!(result, Err(crate::storage::Error::NotFound{kind, ..}) if kind == Kind::Device )
+    );
 }
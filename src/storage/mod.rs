@@ -9,13 +9,16 @@ pub mod mount;
 pub mod pool;
 
 pub use manager::StorageManager;
-pub use mount::{mountinfo, mountpoint, mounts, Mount};
+pub use mount::{
+    cached_mounts, invalidate_mounts, is_mounted, mountinfo, mountpoint, mounts, Mount,
+};
 
 #[derive(Debug, PartialEq, Eq)]
 pub enum Kind {
     Volume,
     Disk,
     Device,
+    Pool,
 }
 
 impl Display for Kind {
@@ -24,6 +27,7 @@ impl Display for Kind {
             Self::Volume => write!(f, "volume"),
             Self::Disk => write!(f, "disk"),
             Self::Device => write!(f, "device"),
+            Self::Pool => write!(f, "pool"),
         }
     }
 }
@@ -42,6 +46,12 @@ pub enum Error {
     #[error("invalid size cannot be '{size}'")]
     InvalidSize { size: Unit },
 
+    #[error("invalid name '{name}'")]
+    InvalidName { name: String },
+
+    #[error("volume '{volume}' is not empty")]
+    NotEmpty { volume: String },
+
     #[error("pool error: {0}")]
     Pool(#[from] pool::Error),
 
@@ -65,7 +75,27 @@ impl Usage {
     // inside this device. basically means that
     // self.used + size <= self.size
     pub fn enough_for(&self, size: Unit) -> bool {
-        self.used + size < self.size
+        self.used.saturating_add(size) <= self.size
+    }
+
+    // remaining returns the free space left on this device
+    pub fn remaining(&self) -> Unit {
+        self.size.saturating_sub(self.used)
+    }
+
+    // free is the unused space in bytes, same as remaining
+    pub fn free(&self) -> Unit {
+        self.remaining()
+    }
+
+    // percent_used returns the used space as a percentage (0 to 100) of
+    // the total size. A zero sized device is always 0% used
+    pub fn percent_used(&self) -> f64 {
+        if self.size == 0 {
+            return 0.0;
+        }
+
+        self.used as f64 / self.size as f64 * 100.0
     }
 }
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -90,6 +120,56 @@ where
 pub struct DiskInfo {
     pub path: PathBuf,
     pub size: Unit,
+    // actual space used by the disk file on the pool, this can be less
+    // than size for sparse files
+    #[serde(default)]
+    pub used: Unit,
+    // copy on write is disabled for the disk file. disks with copy on
+    // write perform badly
+    #[serde(default)]
+    pub nocow: bool,
+}
+
+// pool information as returned by Manager::pools
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PoolInfo {
+    pub name: String,
+    pub state: pool::State,
+    pub device_type: device::DeviceType,
+    // used space is only known for pools that are up
+    pub usage: Usage,
+}
+
+// result of Manager::disk_inspect
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DiskInspect {
+    pub size: Unit,
+    pub used: Unit,
+    // filesystem (or partition table) found on the disk, None if the disk
+    // is raw (empty or unknown content)
+    pub detected_fs: Option<String>,
+}
+
+// overall storage health as reported by Manager::health
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct StorageHealth {
+    // total and used space of ssd pools
+    pub ssd: Usage,
+    // total and allocated space of hdd devices
+    pub hdd: Usage,
+    pub pools_up: usize,
+    pub pools_down: usize,
+    // names of pools (or devices) that are not usable
+    pub broken: Vec<String>,
+}
+
+// how disk file space is allocated
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DiskMode {
+    // blocks are allocated on write (thin provisioning)
+    Sparse,
+    // all blocks are allocated on creation
+    Preallocated,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -114,31 +194,129 @@ pub trait Manager {
     /// delete volume by name. If volume not found, return Ok
     async fn volume_delete<S: AsRef<str> + Send + Sync>(&self, name: S) -> Result<()>;
 
+    /// delete volume by name. If force is false and the volume still
+    /// has files in it, NotEmpty error is returned and nothing is deleted
+    async fn volume_delete_force<S: AsRef<str> + Send + Sync>(
+        &self,
+        name: S,
+        force: bool,
+    ) -> Result<()>;
+
+    /// rename volume with name old to new
+    async fn volume_rename<S: AsRef<str> + Send + Sync, N: AsRef<str> + Send + Sync>(
+        &self,
+        old: S,
+        new: N,
+    ) -> Result<()>;
+
+    /// move volume with name to the ssd pool target_pool. The volume content
+    /// is copied to a new volume (with the same size limit) on the target
+    /// pool, then the source volume is deleted.
+    async fn volume_migrate<S: AsRef<str> + Send + Sync>(
+        &mut self,
+        name: S,
+        target_pool: S,
+    ) -> Result<VolumeInfo>;
+
     /// list all available disks
     async fn disks(&self) -> Result<Vec<DiskInfo>>;
 
     /// look up disk by name
     async fn disk_lookup<S: AsRef<str> + Send + Sync>(&self, name: S) -> Result<DiskInfo>;
 
-    /// create a disk with given name and size
+    /// create a disk with given name and size, with space allocated according to mode
     async fn disk_create<S: AsRef<str> + Send + Sync>(
         &mut self,
         name: S,
         size: Unit,
+        mode: DiskMode,
     ) -> Result<DiskInfo>;
 
     /// delete disk with name
     async fn disk_delete<S: AsRef<str> + Send + Sync>(&self, name: S) -> Result<()>;
 
-    /// expand disk to given size which must be bigger than previous size
+    /// expand disk to given size which must be bigger than previous size.
+    /// only the disk file is extended, resizing the filesystem inside the
+    /// disk is the caller's responsibility
     async fn disk_expand<S: AsRef<str> + Send + Sync>(&self, name: S, size: Unit) -> Result<()>;
 
+    /// inspect disk content to detect the filesystem (btrfs, ext4, vfat)
+    /// or partition table (gpt) it has
+    async fn disk_inspect<S: AsRef<str> + Send + Sync>(&self, name: S) -> Result<DiskInspect>;
+
     /// list all allocated devices
     async fn devices(&self) -> Result<Vec<DeviceInfo>>;
 
+    /// overall health of storage: space per device type, pool states and broken pools
+    async fn health(&self) -> Result<StorageHealth>;
+
+    /// list all ssd and hdd pools with their state and usage
+    async fn pools(&self) -> Result<Vec<PoolInfo>>;
+
+    /// run filesystem maintenance (balance, defragment) on all pools that are up.
+    /// failures are logged and don't stop maintenance of other pools
+    async fn maintenance(&self) -> Result<()>;
+
     /// look up device by name
     async fn device_lookup<S: AsRef<str> + Send + Sync>(&self, name: S) -> Result<DeviceInfo>;
 
     /// device allocate takes the first free HDD that can fullfil the given min size
     async fn device_allocate(&mut self, min: Unit) -> Result<DeviceInfo>;
+
+    /// device release deletes the allocated device and brings the pool down if
+    /// it has no other volumes
+    async fn device_release<S: AsRef<str> + Send + Sync>(&mut self, name: S) -> Result<()>;
+}
+
+#[cfg(test)]
+mod test {
+    use super::Usage;
+
+    #[test]
+    fn usage_enough_for() {
+        let usage = Usage {
+            size: 100,
+            used: 40,
+        };
+
+        assert!(usage.enough_for(59));
+        assert!(usage.enough_for(60));
+        assert!(!usage.enough_for(61));
+        assert_eq!(usage.remaining(), 60);
+    }
+
+    #[test]
+    fn usage_enough_for_overflow() {
+        let usage = Usage {
+            size: u64::MAX - 1,
+            used: u64::MAX - 10,
+        };
+
+        assert!(usage.enough_for(9));
+        assert!(!usage.enough_for(10));
+        assert!(!usage.enough_for(u64::MAX));
+        assert_eq!(usage.remaining(), 9);
+
+        let usage = Usage { size: 10, used: 20 };
+        assert_eq!(usage.remaining(), 0);
+        assert!(!usage.enough_for(1));
+    }
+
+    #[test]
+    fn usage_percent_used() {
+        let usage = Usage {
+            size: 200,
+            used: 50,
+        };
+        assert_eq!(usage.percent_used(), 25.0);
+        assert_eq!(usage.free(), 150);
+
+        let usage = Usage { size: 0, used: 0 };
+        assert_eq!(usage.percent_used(), 0.0);
+        assert_eq!(usage.free(), 0);
+
+        let usage = Usage { size: 0, used: 10 };
+        assert_eq!(usage.percent_used(), 0.0);
+        assert_eq!(usage.free(), 0);
+    }
 }
@@ -1,10 +1,23 @@
+use crate::system::{self, Syscalls};
 use anyhow::{Context, Result};
+use futures::{Stream, TryStreamExt};
+use nix::mount::MsFlags;
+use std::future::Future;
 use std::path::Path;
 use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 use tokio::{fs::OpenOptions, io::AsyncBufRead, io::AsyncBufReadExt, io::BufReader};
 
 const MOUNT_INFO: &str = "/proc/mounts";
+// how long a parsed snapshot of MOUNT_INFO is reused
+const MOUNTS_TTL: Duration = Duration::from_secs(1);
 
+lazy_static::lazy_static! {
+    static ref MOUNTS: MountsCache = MountsCache::new(MOUNTS_TTL);
+}
+
+#[derive(Debug, Clone)]
 pub struct Mount {
     pub source: String,
     pub target: PathBuf,
@@ -34,71 +47,291 @@ impl Mount {
     }
 }
 
+type Snapshot = Option<(Instant, Arc<Vec<Mount>>)>;
+
+// MountsCache keeps a parsed snapshot of the mounts for a short time
+struct MountsCache {
+    ttl: Duration,
+    // generation is bumped on each invalidation so a load that started
+    // before an invalidation does not store a stale snapshot
+    inner: Mutex<(u64, Snapshot)>,
+}
+
+impl MountsCache {
+    fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            inner: Mutex::new((0, None)),
+        }
+    }
+
+    async fn get<F, Fut>(&self, load: F) -> Result<Arc<Vec<Mount>>>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<Vec<Mount>>>,
+    {
+        let generation = {
+            let inner = self.inner.lock().unwrap();
+            if let Some(mounts) = self.fresh(&inner.1) {
+                return Ok(mounts);
+            }
+            inner.0
+        };
+
+        let mounts = Arc::new(load().await?);
+        let mut inner = self.inner.lock().unwrap();
+        if inner.0 == generation {
+            inner.1 = Some((Instant::now(), Arc::clone(&mounts)));
+        }
+
+        Ok(mounts)
+    }
+
+    // cached returns the snapshot only if it's not expired
+    fn cached(&self) -> Option<Arc<Vec<Mount>>> {
+        self.fresh(&self.inner.lock().unwrap().1)
+    }
+
+    fn fresh(&self, snapshot: &Snapshot) -> Option<Arc<Vec<Mount>>> {
+        match snapshot {
+            Some((at, mounts)) if at.elapsed() < self.ttl => Some(Arc::clone(mounts)),
+            _ => None,
+        }
+    }
+
+    fn invalidate(&self) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.0 += 1;
+        inner.1 = None;
+    }
+}
+
+/// cached_mounts is like mounts but reuses the parsed mounts list if it was
+/// read less than a second ago. Use it for lookups that are done frequently.
+pub async fn cached_mounts() -> Result<Arc<Vec<Mount>>> {
+    MOUNTS.get(mounts).await
+}
+
+/// invalidate_mounts drops the cached mounts list, this must be called after
+/// each mount or unmount operation
+pub fn invalidate_mounts() {
+    MOUNTS.invalidate()
+}
+
+/// mount is the same as Syscalls::mount but also drops the cached mounts list.
+/// Use it instead of calling sys.mount directly.
+pub fn mount<Sys, S, T, F, D>(
+    sys: &Sys,
+    source: Option<S>,
+    target: T,
+    fstype: Option<F>,
+    flags: MsFlags,
+    data: Option<D>,
+) -> std::result::Result<(), system::Error>
+where
+    Sys: Syscalls,
+    S: AsRef<Path>,
+    T: AsRef<Path>,
+    F: AsRef<str>,
+    D: AsRef<str>,
+{
+    let result = sys.mount(source, target, fstype, flags, data);
+    invalidate_mounts();
+    result
+}
+
+/// umount_retry is the same as system::umount_retry but also drops the
+/// cached mounts list.
+pub async fn umount_retry<Sys: Syscalls, T: AsRef<Path>>(
+    sys: &Sys,
+    target: T,
+    attempts: usize,
+    detach: bool,
+) -> std::result::Result<(), system::Error> {
+    let result = system::umount_retry(sys, target, attempts, detach).await;
+    invalidate_mounts();
+    result
+}
+
 /// mountpoint returns mount information of target if mount exists
 pub async fn mountpoint<P: AsRef<Path>>(target: P) -> Result<Option<Mount>> {
-    let mounts = mounts().await?;
-    let target = target.as_ref();
-    Ok(mounts.into_iter().find(|m| m.target == target))
+    mountpoint_in(&MOUNTS, mounts_stream, target.as_ref()).await
+}
+
+// mountpoint_in looks up target in the cached snapshot. If there is none, the
+// mounts are read from open only up to the target mount. A partial read is
+// not cached.
+async fn mountpoint_in<F, S>(cache: &MountsCache, open: F, target: &Path) -> Result<Option<Mount>>
+where
+    F: FnOnce() -> S,
+    S: Stream<Item = Result<Mount>>,
+{
+    if let Some(mounts) = cache.cached() {
+        return Ok(find_mountpoint(&mounts, target).cloned());
+    }
+
+    let mounts = open();
+    futures::pin_mut!(mounts);
+    while let Some(mount) = mounts.try_next().await? {
+        if mount.target == target {
+            return Ok(Some(mount));
+        }
+    }
+
+    Ok(None)
+}
+
+/// is_mounted returns true only if target is a mount point. Failing to read
+/// the mounts is returned as an error, not as "not mounted"
+pub async fn is_mounted<P: AsRef<Path>>(target: P) -> Result<bool> {
+    is_mounted_in(&MOUNTS, mounts, target.as_ref()).await
+}
+
+async fn is_mounted_in<F, Fut>(cache: &MountsCache, load: F, target: &Path) -> Result<bool>
+where
+    F: FnOnce() -> Fut,
+    Fut: Future<Output = Result<Vec<Mount>>>,
+{
+    let mounts = cache.get(load).await?;
+    Ok(find_mountpoint(&mounts, target).is_some())
+}
+
+// find_mountpoint stops at the first mount on target
+fn find_mountpoint<'a>(mounts: &'a [Mount], target: &Path) -> Option<&'a Mount> {
+    mounts.iter().find(|m| m.target == target)
 }
 
 /// mount info returns mount information of source mount. if source (say a disk or disk parition) is mounted
 /// multiple times Vec will have more than one element.
 /// note that source is not a "path" because source can be other things
 pub async fn mountinfo<P: AsRef<str>>(source: P) -> Result<Vec<Mount>> {
-    let mounts = mounts().await?;
+    let mounts = cached_mounts().await?;
     let source = source.as_ref();
-    Ok(mounts.into_iter().filter(|m| m.source == source).collect())
+    Ok(mounts
+        .iter()
+        .filter(|m| m.source == source)
+        .cloned()
+        .collect())
 }
 
 /// list all mounts on the system
 pub async fn mounts() -> Result<Vec<Mount>> {
-    let file = OpenOptions::new().read(true).open(MOUNT_INFO).await?;
+    mounts_stream().try_collect().await
+}
 
-    parser_reader(BufReader::new(file)).await
+/// mounts_stream yields the mounts on the system one by one as they
+/// are read. Prefer it over mounts if you are looking for a single mount
+pub fn mounts_stream() -> impl Stream<Item = Result<Mount>> {
+    futures::stream::once(async {
+        OpenOptions::new()
+            .read(true)
+            .open(MOUNT_INFO)
+            .await
+            .context("failed to open mounts info")
+    })
+    .map_ok(|file| parser_stream(BufReader::new(file)))
+    .try_flatten()
 }
 
+#[cfg(test)]
 async fn parser_reader<R: AsyncBufRead + Unpin>(reader: R) -> Result<Vec<Mount>> {
-    let mut lines = reader.lines();
-    let mut mounts = vec![];
-    while let Some(line) = lines.next_line().await? {
-        // parse each line
-        /* EXAMPLES
-        proc /proc proc rw,nosuid,nodev,noexec,relatime 0 0
-        sys /sys sysfs rw,nosuid,nodev,noexec,relatime 0 0
-        dev /dev devtmpfs rw,nosuid,relatime,size=8087648k,nr_inodes=2021912,mode=755,inode64 0 0
-        run /run tmpfs rw,nosuid,nodev,relatime,mode=755,inode64 0 0
-        efivarfs /sys/firmware/efi/efivars efivarfs rw,nosuid,nodev,noexec,relatime 0 0
-        devpts /dev/pts devpts rw,nosuid,noexec,relatime,gid=5,mode=620,ptmxmode=000 0 0
-        /dev/sdb2 / btrfs rw,relatime,ssd,space_cache,subvolid=256,subvol=/root 0 0
-        */
-        let parts: Vec<&str> = line.split_whitespace().collect();
-        if parts.len() != 6 {
-            log::error!("invalid mount info line '{}'", line);
-            continue;
+    parser_stream(reader).try_collect().await
+}
+
+fn parser_stream<R: AsyncBufRead + Unpin>(reader: R) -> impl Stream<Item = Result<Mount>> {
+    // state is set to None after an error so the stream ends
+    futures::stream::unfold(Some(reader.lines()), |lines| async move {
+        let mut lines = lines?;
+        loop {
+            let line = match lines.next_line().await {
+                Ok(Some(line)) => line,
+                Ok(None) => return None,
+                Err(err) => return Some((Err(err.into()), None)),
+            };
+
+            match parse_line(&line) {
+                Ok(Some(mount)) => return Some((Ok(mount), Some(lines))),
+                Ok(None) => continue,
+                Err(err) => return Some((Err(err), None)),
+            }
         }
-        let mount = Mount {
-            source: parts[0].into(),
-            target: parts[1].into(),
-            filesystem: parts[2].into(),
-            options: parts[3].into(),
-            dump: parts[4]
-                .parse()
-                .with_context(|| format!("invalid dump value from line {}", line))?,
-            pass: parts[5]
-                .parse()
-                .with_context(|| format!("invalid pass value from line {}", line))?,
+    })
+}
+
+// parse_line parses a single mounts line. returns None if the line is not valid
+fn parse_line(line: &str) -> Result<Option<Mount>> {
+    /* EXAMPLES
+    proc /proc proc rw,nosuid,nodev,noexec,relatime 0 0
+    sys /sys sysfs rw,nosuid,nodev,noexec,relatime 0 0
+    dev /dev devtmpfs rw,nosuid,relatime,size=8087648k,nr_inodes=2021912,mode=755,inode64 0 0
+    run /run tmpfs rw,nosuid,nodev,relatime,mode=755,inode64 0 0
+    efivarfs /sys/firmware/efi/efivars efivarfs rw,nosuid,nodev,noexec,relatime 0 0
+    devpts /dev/pts devpts rw,nosuid,noexec,relatime,gid=5,mode=620,ptmxmode=000 0 0
+    /dev/sdb2 / btrfs rw,relatime,ssd,space_cache,subvolid=256,subvol=/root 0 0
+    */
+    let parts: Vec<&str> = line.split_whitespace().collect();
+    if parts.len() != 6 {
+        log::error!("invalid mount info line '{}'", line);
+        return Ok(None);
+    }
+
+    Ok(Some(Mount {
+        source: unescape(parts[0]),
+        target: unescape(parts[1]).into(),
+        filesystem: parts[2].into(),
+        options: unescape(parts[3]),
+        dump: parts[4]
+            .parse()
+            .with_context(|| format!("invalid dump value from line {}", line))?,
+        pass: parts[5]
+            .parse()
+            .with_context(|| format!("invalid pass value from line {}", line))?,
+    }))
+}
+
+// unescape decodes the octal escapes the kernel uses for white spaces
+// and backslashes in mount fields (\040, \011, \012 and \134)
+fn unescape(field: &str) -> String {
+    if !field.contains('\\') {
+        return field.into();
+    }
+
+    let mut out = String::with_capacity(field.len());
+    let mut rest = field;
+    while let Some(index) = rest.find('\\') {
+        out.push_str(&rest[..index]);
+        rest = &rest[index..];
+        let decoded = match rest.get(1..4) {
+            Some("040") => ' ',
+            Some("011") => '\t',
+            Some("012") => '\n',
+            Some("134") => '\\',
+            _ => {
+                // not an escape we know, keep it as is
+                out.push('\\');
+                rest = &rest[1..];
+                continue;
+            }
         };
-        mounts.push(mount);
+        out.push(decoded);
+        rest = &rest[4..];
     }
+    out.push_str(rest);
 
-    Ok(mounts)
+    out
 }
 
 #[cfg(test)]
 mod test {
-    use super::Mount;
-    use std::path::PathBuf;
-    use tokio::io::BufReader;
+    use super::{Mount, MountsCache};
+    use crate::system::{Error, Syscalls};
+    use nix::mount::{MntFlags, MsFlags};
+    use std::path::{Path, PathBuf};
+    use std::pin::Pin;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::task::{Context, Poll};
+    use std::time::Duration;
+    use tokio::io::{AsyncRead, AsyncReadExt, BufReader, ReadBuf};
 
     const MOUNTS: &str = r#"
 tmpfs / tmpfs rw,relatime,size=1572864k 0 0
@@ -201,4 +434,172 @@ overlay /var/cache/modules/flistd/mountpoint/647-10988-vm overlay rw,noatime,low
 
         assert_eq!(mnt.target, PathBuf::from("/"));
     }
+
+    #[tokio::test]
+    async fn mounts_cache() {
+        let cache = MountsCache::new(Duration::from_secs(60));
+        let parsed = AtomicUsize::new(0);
+        let load = || async {
+            parsed.fetch_add(1, Ordering::SeqCst);
+            super::parser_reader(BufReader::new(MOUNTS.as_bytes())).await
+        };
+
+        let is_mounted =
+            |mounts: &[Mount]| mounts.iter().any(|m| m.target == Path::new("/var/cache"));
+
+        for _ in 0..5 {
+            let mounts = cache.get(load).await.expect("failed to get mounts");
+            assert!(is_mounted(&mounts));
+        }
+        assert_eq!(parsed.load(Ordering::SeqCst), 1);
+
+        // mount or unmount drops the snapshot
+        cache.invalidate();
+        cache.get(load).await.expect("failed to get mounts");
+        assert_eq!(parsed.load(Ordering::SeqCst), 2);
+
+        // snapshot expires after ttl
+        let cache = MountsCache::new(Duration::ZERO);
+        cache.get(load).await.expect("failed to get mounts");
+        cache.get(load).await.expect("failed to get mounts");
+        assert_eq!(parsed.load(Ordering::SeqCst), 4);
+    }
+
+    #[tokio::test]
+    async fn is_mounted() {
+        let cache = MountsCache::new(Duration::from_secs(60));
+        let parsed = AtomicUsize::new(0);
+        let load = || async {
+            parsed.fetch_add(1, Ordering::SeqCst);
+            super::parser_reader(BufReader::new(MOUNTS.as_bytes())).await
+        };
+        let is_mounted =
+            |target: &'static str| super::is_mounted_in(&cache, load, Path::new(target));
+
+        assert!(is_mounted("/var/cache").await.unwrap());
+        assert!(is_mounted("/var/run/netns/ndmz").await.unwrap());
+        // existing directories that are not mount points
+        assert!(!is_mounted("/var").await.unwrap());
+        assert!(!is_mounted("/var/cache/modules").await.unwrap());
+
+        // all calls within the ttl parsed the mounts once
+        assert_eq!(parsed.load(Ordering::SeqCst), 1);
+
+        // errors reading the mounts are not reported as "not mounted"
+        let cache = MountsCache::new(Duration::from_secs(60));
+        let load =
+            || super::parser_reader(BufReader::new("/dev/sda /mnt btrfs rw x 0\n".as_bytes()));
+        assert!(super::is_mounted_in(&cache, load, Path::new("/mnt"))
+            .await
+            .is_err());
+    }
+
+    struct FailSyscalls;
+
+    impl Syscalls for FailSyscalls {
+        fn mount<S: AsRef<Path>, T: AsRef<Path>, F: AsRef<str>, D: AsRef<str>>(
+            &self,
+            _source: Option<S>,
+            _target: T,
+            _fstype: Option<F>,
+            _flags: MsFlags,
+            _data: Option<D>,
+        ) -> Result<(), Error> {
+            Err(Error::Unix(nix::errno::Errno::EPERM))
+        }
+
+        fn umount<T: AsRef<Path>>(
+            &self,
+            _target: T,
+            _flags: Option<MntFlags>,
+        ) -> Result<(), Error> {
+            Err(Error::Unix(nix::errno::Errno::EINVAL))
+        }
+    }
+
+    #[tokio::test]
+    async fn mount_invalidates() {
+        let generation = || super::MOUNTS.inner.lock().unwrap().0;
+
+        // the mounts list is dropped even if the mount call fails
+        let before = generation();
+        assert!(super::mount(
+            &FailSyscalls,
+            None::<&str>,
+            "/mnt/target",
+            None::<&str>,
+            MsFlags::empty(),
+            None::<&str>
+        )
+        .is_err());
+        assert!(generation() > before);
+
+        let before = generation();
+        assert!(super::umount_retry(&FailSyscalls, "/mnt/target", 1, false)
+            .await
+            .is_err());
+        assert!(generation() > before);
+    }
+
+    // a reader that fails the test if it's ever read from
+    struct PanicReader;
+
+    impl AsyncRead for PanicReader {
+        fn poll_read(
+            self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+            _buf: &mut ReadBuf<'_>,
+        ) -> Poll<std::io::Result<()>> {
+            panic!("read past the target mount");
+        }
+    }
+
+    #[tokio::test]
+    async fn mountpoint_short_circuit() {
+        const HEAD: &str = "tmpfs / tmpfs rw,relatime,size=1572864k 0 0
+proc /proc proc rw,relatime 0 0
+/dev/sda /var/cache btrfs rw,relatime,ssd,space_cache,subvolid=256,subvol=/zos-cache 0 0
+";
+        // no snapshot, so the mounts are only read up to the target
+        let cache = MountsCache::new(Duration::from_secs(60));
+        let open = || super::parser_stream(BufReader::new(HEAD.as_bytes().chain(PanicReader)));
+        let mount = super::mountpoint_in(&cache, open, Path::new("/var/cache"))
+            .await
+            .expect("failed to find mountpoint")
+            .expect("mount not found");
+
+        assert_eq!(mount.source, "/dev/sda");
+        assert!(matches!(mount.option("subvol"), Some(Some(v)) if v == "/zos-cache"));
+
+        // with a snapshot the mounts are not read at all
+        cache
+            .get(|| super::parser_reader(BufReader::new(MOUNTS.as_bytes())))
+            .await
+            .unwrap();
+        let open = || super::parser_stream(BufReader::new(PanicReader));
+        let mount = super::mountpoint_in(&cache, open, Path::new("/var/run/netns/ndmz"))
+            .await
+            .expect("failed to find mountpoint")
+            .expect("mount not found");
+        assert_eq!(mount.filesystem, "nsfs");
+    }
+
+    #[tokio::test]
+    async fn parser_escaped() {
+        const LINES: &str = r"/dev/sdb\040disk /mnt/my\040data btrfs rw,subvol=/a\011b 0 0
+/dev/sdc /mnt/back\134slash\012x ext4 rw 0 0
+/dev/sdd /mnt/odd\04 ext4 rw 0 0
+";
+        let mounts = super::parser_reader(BufReader::new(LINES.as_bytes()))
+            .await
+            .expect("failed to parse mounts list");
+
+        assert_eq!(mounts.len(), 3);
+        assert_eq!(mounts[0].source, "/dev/sdb disk");
+        assert_eq!(mounts[0].target, PathBuf::from("/mnt/my data"));
+        assert!(matches!(mounts[0].option("subvol"), Some(Some(v)) if v == "/a\tb"));
+        assert_eq!(mounts[1].target, PathBuf::from("/mnt/back\\slash\nx"));
+        // incomplete escapes are left untouched
+        assert_eq!(mounts[2].target, PathBuf::from(r"/mnt/odd\04"));
+    }
 }
@@ -1,13 +1,24 @@
-use super::{DownPool, Error, InvalidDevice, Pool, PoolManager, Result, UpPool, Usage, Volume};
+use super::{
+    DownPool, Error, InvalidDevice, Pool, PoolManager, Result, UpPool, Usage, Volume,
+    UMOUNT_ATTEMPTS,
+};
 use crate::storage::device::{Device, DeviceManager, Filesystem};
 use crate::system::{Command, Executor, Syscalls};
 use crate::Unit;
 use anyhow::Context;
+use std::collections::HashMap;
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::time::Duration;
 
 /// root mount path
 const MNT: &str = "/mnt";
+// only data chunks used less than this percent are balanced during maintenance
+const BALANCE_USAGE_THRESHOLD: u8 = 50;
+// balance and defragment can run for a very long time on a big pool,
+// they are killed if they don't finish within these timeouts
+const BALANCE_TIMEOUT: Duration = Duration::from_secs(60 * 60);
+const DEFRAGMENT_TIMEOUT: Duration = Duration::from_secs(60 * 60);
 
 /// dir size will calculate the total size of a directory including sub directories
 pub async fn dir_size<P: Into<PathBuf>>(root: P) -> std::result::Result<Unit, std::io::Error> {
@@ -43,11 +54,41 @@ where
 
 impl<E> BtrfsVolume<E>
 where
-    E: Executor,
+    E: Executor + Send + Sync + 'static,
 {
     fn new(utils: Arc<BtrfsUtils<E>>, id: u64, path: PathBuf) -> Self {
         Self { utils, id, path }
     }
+
+    /// snapshot creates a snapshot of this volume with the given name
+    /// in the same pool and returns it as a new volume. If readonly is set
+    /// the snapshot can't be modified.
+    pub async fn snapshot<S: AsRef<str>>(&self, name: S, readonly: bool) -> Result<Self> {
+        let root = self.path.parent().ok_or_else(|| Error::VolumeNotFound {
+            volume: self.path.to_string_lossy().into(),
+        })?;
+
+        let path = self
+            .utils
+            .volume_snapshot(&self.path, root, &name, readonly)
+            .await?;
+        let id = self.utils.volume_id(root, &name).await?;
+
+        Ok(Self::new(Arc::clone(&self.utils), id, path))
+    }
+
+    async fn qgroup(&self) -> Result<QGroupInfo> {
+        let qgroup = self
+            .utils
+            .qgroup_list(&self.path)
+            .await?
+            .into_iter()
+            .find(|g| g.id == format!("0/{}", self.id));
+
+        qgroup.ok_or_else(|| Error::QGroupNotFound {
+            volume: self.path.clone(),
+        })
+    }
 }
 
 #[async_trait::async_trait]
@@ -75,25 +116,16 @@ where
     }
 
     async fn usage(&self) -> Result<Unit> {
-        let qgroup = self
-            .utils
-            .qgroup_list(&self.path)
-            .await?
-            .into_iter()
-            .find(|g| g.id == format!("0/{}", self.id));
-
-        let qgroup = qgroup.ok_or_else(|| Error::QGroupNotFound {
-            volume: self.path.clone(),
-        })?;
+        Ok(self.qgroup().await?.used())
+    }
 
-        let used = match qgroup.max_rfer {
-            Some(used) => used,
-            None => dir_size(&self.path)
-                .await
-                .context("failed to calculate volume size")?, //TODO: scan all files sizes
-        };
+    async fn quota(&self) -> Result<Option<Unit>> {
+        Ok(self.qgroup().await?.max_rfer)
+    }
 
-        Ok(used)
+    async fn rename<S: AsRef<str> + Send>(&self, new: S) -> Result<()> {
+        // moving the subvolume keeps its id and hence its qgroup
+        super::rename_volume(&self.path, new).await
     }
 }
 
@@ -125,7 +157,8 @@ where
                 reason: InvalidDevice::InvalidLabel,
             })?);
 
-        self.sys.mount(
+        crate::storage::mount::mount(
+            &self.sys,
             Some(self.device.path()),
             &path,
             Option::<&str>::None,
@@ -195,6 +228,19 @@ where
             path,
         }
     }
+
+    /// balance the pool data chunks. if usage_threshold is set only
+    /// chunks that are used less than the threshold (percent) are balanced
+    pub async fn balance(&self, usage_threshold: Option<u8>) -> Result<()> {
+        self.utils.balance(&self.path, usage_threshold).await
+    }
+
+    /// defragment all files in the pool. Note that defragmenting snapshotted
+    /// or reflinked files unshares their extents, which can increase the
+    /// used space a lot, hence it's not part of the pool maintenance.
+    pub async fn defragment(&self) -> Result<()> {
+        self.utils.defragment(&self.path).await
+    }
 }
 
 #[async_trait::async_trait]
@@ -221,15 +267,25 @@ where
     }
 
     async fn usage(&self) -> Result<Usage> {
+        // list volumes and qgroups once, then match each volume
+        // with its qgroup.
+        let volumes = self.utils.volume_list(&self.path).await?;
+        let groups: HashMap<String, QGroupInfo> = self
+            .utils
+            .qgroup_list(&self.path)
+            .await?
+            .into_iter()
+            .map(|g| (g.id.clone(), g))
+            .collect();
+
         let mut used: Unit = 0;
-        // this is a very bad implementation because each call to
-        // volume.usage() will list all qgroups first then find the
-        // corresponding volume id.
-        // instead this can be improved by listing once both the
-        // volumes and the groups, then just match and calculate once.
-        // todo!
-        for volume in self.volumes().await? {
-            used += volume.usage().await?;
+        for volume in volumes {
+            used += match groups.get(&format!("0/{}", volume.id)) {
+                Some(group) => group.used(),
+                None => dir_size(self.path.join(&volume.name))
+                    .await
+                    .context("failed to calculate volume size")?,
+            };
         }
 
         Ok(Usage {
@@ -239,7 +295,9 @@ where
     }
 
     async fn down(mut self) -> std::result::Result<Self::DownPool, super::DownError<Self>> {
-        match self.sys.umount(&self.path, None) {
+        match crate::storage::mount::umount_retry(&self.sys, &self.path, UMOUNT_ATTEMPTS, false)
+            .await
+        {
             Ok(_) => Ok(BtrfsDownPool::new(self.utils, self.sys, self.device)),
             Err(err) => Err(super::DownError {
                 pool: self,
@@ -286,6 +344,10 @@ where
             self.path.join(name.as_ref()),
         ))
     }
+
+    async fn maintenance(&self) -> Result<()> {
+        self.balance(Some(BALANCE_USAGE_THRESHOLD)).await
+    }
 }
 
 /// shorthand for a btrfs pool
@@ -384,7 +446,6 @@ where
 
 struct QGroupInfo {
     id: String,
-    #[allow(unused)]
     rfer: Unit,
     #[allow(unused)]
     excl: Unit,
@@ -393,6 +454,15 @@ struct QGroupInfo {
     max_excl: Option<Unit>,
 }
 
+impl QGroupInfo {
+    // used is the space accounted for the volume of this qgroup. a limited
+    // volume reserves its full limit, an unlimited one only what it references.
+    // both the volume and the pool usage must use this so they always agree.
+    fn used(&self) -> Unit {
+        self.max_rfer.unwrap_or(self.rfer)
+    }
+}
+
 struct VolumeInfo {
     id: u64,
     name: String,
@@ -402,7 +472,7 @@ struct BtrfsUtils<E: Executor> {
     exec: E,
 }
 
-impl<E: Executor + 'static> BtrfsUtils<E> {
+impl<E: Executor + Send + Sync + 'static> BtrfsUtils<E> {
     fn new(exec: E) -> Self {
         Self { exec }
     }
@@ -435,6 +505,44 @@ impl<E: Executor + 'static> BtrfsUtils<E> {
         Ok(root.as_ref().join(name.as_ref()))
     }
 
+    async fn volume_snapshot<V: AsRef<Path>, P: AsRef<Path>, S: AsRef<str>>(
+        &self,
+        volume: V,
+        root: P,
+        name: S,
+        readonly: bool,
+    ) -> Result<PathBuf> {
+        let path = root.as_ref().join(name.as_ref());
+        if tokio::fs::metadata(&path).await.is_ok() {
+            return Err(Error::VolumeAlreadyExists {
+                volume: name.as_ref().into(),
+            });
+        }
+
+        let mut cmd = Command::new("btrfs").arg("subvolume").arg("snapshot");
+        if readonly {
+            cmd = cmd.arg("-r");
+        }
+        let cmd = cmd.arg(volume.as_ref()).arg(&path);
+
+        use crate::system::Error as ExecError;
+        match self.exec.run(&cmd).await {
+            Ok(_) => (),
+            Err(ExecError::Exit { code, stderr })
+                if code == 1
+                    && String::from_utf8_lossy(&stderr)
+                        .starts_with("ERROR: target path already exists:") =>
+            {
+                return Err(Error::VolumeAlreadyExists {
+                    volume: name.as_ref().into(),
+                })
+            }
+            Err(err) => return Err(err.into()),
+        };
+
+        Ok(path)
+    }
+
     async fn volume_delete<P: AsRef<Path>, S: AsRef<str>>(&self, root: P, name: S) -> Result<()> {
         let path = root.as_ref().join(name.as_ref());
         let cmd = Command::new("btrfs")
@@ -477,6 +585,28 @@ impl<E: Executor + 'static> BtrfsUtils<E> {
         Ok(self.parse_volumes(&output)?)
     }
 
+    async fn balance<P: AsRef<Path>>(&self, root: P, usage_threshold: Option<u8>) -> Result<()> {
+        let mut cmd = Command::new("btrfs").arg("balance").arg("start");
+        if let Some(usage) = usage_threshold {
+            cmd = cmd.arg(format!("-dusage={}", usage));
+        }
+        let cmd = cmd.arg(root.as_ref());
+
+        self.exec.run_timeout(&cmd, BALANCE_TIMEOUT).await?;
+        Ok(())
+    }
+
+    async fn defragment<P: AsRef<Path>>(&self, root: P) -> Result<()> {
+        let cmd = Command::new("btrfs")
+            .arg("filesystem")
+            .arg("defragment")
+            .arg("-r")
+            .arg(root.as_ref());
+
+        self.exec.run_timeout(&cmd, DEFRAGMENT_TIMEOUT).await?;
+        Ok(())
+    }
+
     async fn qgroup_enable<P: AsRef<Path>>(&self, root: P) -> Result<()> {
         let cmd = Command::new("btrfs")
             .arg("quota")
@@ -607,12 +737,13 @@ impl Default for BtrfsUtils<crate::system::System> {
 
 #[cfg(test)]
 mod test {
-    use super::{BtrfsPool, BtrfsUtils, DownPool, Pool, UpPool, Volume};
+    use super::{BtrfsPool, BtrfsUtils, BtrfsVolume, DownPool, Pool, UpPool, Volume};
     use crate::storage::device::Device;
-    use crate::system::{Command, Syscalls};
+    use crate::system::{Command, RecordingExecutor, Syscalls};
     use crate::Unit;
     use anyhow::Result;
     use std::path::{Path, PathBuf};
+    use std::sync::Arc;
 
     // mock syscall always succeed
     // should be improved to validate the inputs
@@ -670,6 +801,75 @@ mod test {
         }
     }
 
+    #[tokio::test]
+    async fn pool_up_commands() {
+        const VOLS: &str = "ID 256 gen 33152047 top level 5 path zos-cache\n";
+
+        let device = MockDevice {
+            path: "/dev/mock".into(),
+            size: 100 * crate::GIGABYTE,
+            label: "test-device".into(),
+        };
+
+        // quota enable on up, then the volumes list
+        let exec = RecordingExecutor::default().respond("").respond(VOLS);
+        let pool = BtrfsPool::with(exec.clone(), MockSyscalls, device)
+            .await
+            .unwrap();
+        let pool = match pool {
+            Pool::Down(pool) => pool,
+            _ => panic!("invalid pool type returned"),
+        };
+
+        let up = pool.up().await.unwrap();
+        let volumes = up.volumes().await.unwrap();
+        assert_eq!(volumes.len(), 1);
+
+        assert_eq!(
+            exec.commands(),
+            vec![
+                Command::new("btrfs")
+                    .arg("quota")
+                    .arg("enable")
+                    .arg("/mnt/test-device"),
+                Command::new("btrfs")
+                    .arg("subvolume")
+                    .arg("list")
+                    .arg("-o")
+                    .arg("/mnt/test-device"),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn pool_maintenance() {
+        let device = MockDevice {
+            path: "/dev/mock".into(),
+            size: 100 * crate::GIGABYTE,
+            label: "test-device".into(),
+        };
+
+        let exec = RecordingExecutor::default();
+        let up = super::BtrfsUpPool::new(
+            std::sync::Arc::new(BtrfsUtils::new(exec.clone())),
+            MockSyscalls,
+            "/mnt/test-device".into(),
+            device,
+        );
+
+        up.maintenance().await.unwrap();
+
+        // only a filtered balance, defragment unshares extents
+        assert_eq!(
+            exec.commands(),
+            vec![Command::new("btrfs")
+                .arg("balance")
+                .arg("start")
+                .arg("-dusage=50")
+                .arg("/mnt/test-device")]
+        );
+    }
+
     #[tokio::test]
     async fn pool_new() {
         const VOLS: &str = r#"ID 256 gen 33152047 top level 5 path zos-cache"#;
@@ -736,6 +936,113 @@ mod test {
 
         let usage = cache.usage().await.unwrap();
         assert_eq!(usage, 100 * crate::GIGABYTE);
+        let quota = cache.quota().await.unwrap();
+        assert_eq!(quota, Some(100 * crate::GIGABYTE));
+    }
+
+    #[tokio::test]
+    async fn pool_usage() {
+        const VOLS: &str = r#"ID 256 gen 33152047 top level 5 path zos-cache
+ID 262 gen 33152049 top level 5 path vdisks
+ID 1740 gen 33008608 top level 5 path b623b3b159fa02652bb21c695a157b4d
+"#;
+        const GROUPS: &str = r#"qgroupid         rfer         excl     max_rfer     max_excl
+--------         ----         ----     --------     --------
+0/256      1732771840   1732771840 107374182400         none
+0/262     60463501312  60463501312         none         none
+0/1740          16384        16384     10485760         none
+"#;
+
+        let device = MockDevice {
+            path: "/dev/mock".into(),
+            size: 1 * crate::TERABYTE,
+            label: "test-device".into(),
+        };
+
+        let mut exec = crate::system::MockExecutor::default();
+        let list = Command::new("btrfs")
+            .arg("subvolume")
+            .arg("list")
+            .arg("-o")
+            .arg("/mnt/test-device");
+
+        let groups = Command::new("btrfs")
+            .arg("qgroup")
+            .arg("show")
+            .arg("-re")
+            .arg("--raw")
+            .arg("/mnt/test-device");
+
+        exec.expect_run()
+            .withf(move |arg: &Command| arg == &list)
+            .times(1)
+            .returning(|_| Ok(Vec::from(VOLS)));
+
+        exec.expect_run()
+            .withf(move |arg: &Command| arg == &groups)
+            .times(1)
+            .returning(|_| Ok(Vec::from(GROUPS)));
+
+        let up = super::BtrfsUpPool::new(
+            std::sync::Arc::new(BtrfsUtils::new(exec)),
+            MockSyscalls,
+            "/mnt/test-device".into(),
+            device,
+        );
+
+        let usage = up.usage().await.unwrap();
+        assert_eq!(usage.size, 1 * crate::TERABYTE);
+        assert_eq!(usage.used, 107374182400 + 60463501312 + 10485760);
+    }
+
+    #[tokio::test]
+    async fn pool_usage_volumes() {
+        const VOLS: &str = r#"ID 256 gen 33152047 top level 5 path zos-cache
+ID 262 gen 33152049 top level 5 path vdisks
+"#;
+        const GROUPS: &str = r#"qgroupid         rfer         excl     max_rfer     max_excl
+--------         ----         ----     --------     --------
+0/256      1732771840   1732771840 107374182400         none
+0/262     60463501312  60463501312         none         none
+"#;
+
+        let device = MockDevice {
+            path: "/dev/mock".into(),
+            size: 1 * crate::TERABYTE,
+            label: "test-device".into(),
+        };
+
+        let mut exec = crate::system::MockExecutor::default();
+        let list = Command::new("btrfs")
+            .arg("subvolume")
+            .arg("list")
+            .arg("-o")
+            .arg("/mnt/test-device");
+
+        exec.expect_run()
+            .withf(move |arg: &Command| arg == &list)
+            .returning(|_| Ok(Vec::from(VOLS)));
+
+        // qgroups are listed for the pool and for each volume
+        exec.expect_run()
+            .withf(|arg: &Command| arg.to_string().contains(r#""qgroup" "show""#))
+            .returning(|_| Ok(Vec::from(GROUPS)));
+
+        let up = super::BtrfsUpPool::new(
+            std::sync::Arc::new(BtrfsUtils::new(exec)),
+            MockSyscalls,
+            "/mnt/test-device".into(),
+            device,
+        );
+
+        let mut used = 0;
+        for volume in up.volumes().await.unwrap() {
+            used += volume.usage().await.unwrap();
+        }
+
+        // the unlimited volume is accounted with its referenced size
+        assert_eq!(used, 107374182400 + 60463501312);
+        assert_eq!(up.usage().await.unwrap().used, used);
     }
 
     #[test]
@@ -868,6 +1175,74 @@ ID 7438 gen 33152049 top level 5 path rootfs:647-10988-vm
         assert!(matches!(vol, Err(Error::VolumeAlreadyExists { volume }) if volume == "test"))
     }
 
+    #[tokio::test]
+    async fn volume_snapshot() {
+        const DATA: &str = r#"backup
+        Name: 			backup
+        UUID: 			abf4240e-6402-9947-963e-63db1a7f5582
+        Parent UUID: 		-
+        Received UUID: 		-
+        Creation time: 		2022-02-03 12:58:32 +0000
+        Subvolume ID: 		1741
+        Generation: 		33008608
+        Gen at creation: 	199304
+        Parent ID: 		5
+        Top level ID: 		5
+        Flags: 			readonly
+        Snapshot(s):
+        "#;
+
+        for readonly in [true, false] {
+            let mut exec = crate::system::MockExecutor::default();
+            let mut cmd = Command::new("btrfs").arg("subvolume").arg("snapshot");
+            if readonly {
+                cmd = cmd.arg("-r");
+            }
+            let cmd = cmd.arg("/mnt/pool/test").arg("/mnt/pool/backup");
+            exec.expect_run()
+                .withf(move |arg: &Command| arg == &cmd)
+                .times(1)
+                .returning(|_| Ok(Vec::default()));
+
+            let cmd = Command::new("btrfs")
+                .arg("subvolume")
+                .arg("show")
+                .arg("/mnt/pool/backup");
+            exec.expect_run()
+                .withf(move |arg: &Command| arg == &cmd)
+                .times(1)
+                .returning(|_| Ok(Vec::from(DATA)));
+
+            let utils = Arc::new(BtrfsUtils::new(exec));
+            let volume = BtrfsVolume::new(utils, 1740, "/mnt/pool/test".into());
+            let snapshot = volume.snapshot("backup", readonly).await.unwrap();
+            assert_eq!(snapshot.id(), 1741);
+            assert_eq!(snapshot.path(), Path::new("/mnt/pool/backup"));
+            assert_eq!(snapshot.name(), "backup");
+        }
+    }
+
+    #[tokio::test]
+    async fn volume_snapshot_exists() {
+        use crate::storage::pool::Error;
+        use crate::system::Error as ExecError;
+
+        let mut exec = crate::system::MockExecutor::default();
+        exec.expect_run().times(1).returning(|_| {
+            Err(ExecError::Exit {
+                code: 1,
+                stderr: "ERROR: target path already exists: /mnt/pool/backup".into(),
+            })
+        });
+
+        let utils = Arc::new(BtrfsUtils::new(exec));
+        let volume = BtrfsVolume::new(utils, 1740, "/mnt/pool/test".into());
+        let snapshot = volume.snapshot("backup", false).await;
+        assert!(
+            matches!(snapshot, Err(Error::VolumeAlreadyExists { volume }) if volume == "backup")
+        );
+    }
+
     #[tokio::test]
     async fn utils_volume_delete() {
         let exec = crate::system::MockExecutor::default();
@@ -987,6 +1362,60 @@ ID 7438 gen 33152049 top level 5 path rootfs:647-10988-vm
         assert_eq!(vol1.name, "vdisks");
     }
 
+    #[tokio::test]
+    async fn utils_balance() {
+        let exec = crate::system::MockExecutor::default();
+        let mut utils = BtrfsUtils::new(exec);
+        let cmd = Command::new("btrfs")
+            .arg("balance")
+            .arg("start")
+            .arg("/mnt/pool");
+        utils
+            .exec
+            .expect_run_timeout()
+            .withf(move |arg: &Command, _| arg == &cmd)
+            .times(1)
+            .returning(|_, _| Ok(Vec::default()));
+
+        utils.balance("/mnt/pool", None).await.unwrap();
+        utils.exec.checkpoint();
+
+        let cmd = Command::new("btrfs")
+            .arg("balance")
+            .arg("start")
+            .arg("-dusage=50")
+            .arg("/mnt/pool");
+        utils
+            .exec
+            .expect_run_timeout()
+            .withf(move |arg: &Command, _| arg == &cmd)
+            .times(1)
+            .returning(|_, _| Ok(Vec::default()));
+
+        utils.balance("/mnt/pool", Some(50)).await.unwrap();
+        utils.exec.checkpoint();
+    }
+
+    #[tokio::test]
+    async fn utils_defragment() {
+        let exec = crate::system::MockExecutor::default();
+        let mut utils = BtrfsUtils::new(exec);
+        let cmd = Command::new("btrfs")
+            .arg("filesystem")
+            .arg("defragment")
+            .arg("-r")
+            .arg("/mnt/pool");
+        utils
+            .exec
+            .expect_run_timeout()
+            .withf(move |arg: &Command, _| arg == &cmd)
+            .times(1)
+            .returning(|_, _| Ok(Vec::default()));
+
+        utils.defragment("/mnt/pool").await.unwrap();
+        utils.exec.checkpoint();
+    }
+
     #[tokio::test]
     async fn utils_qgroup_enable() {
         let exec = crate::system::MockExecutor::default();
@@ -0,0 +1,536 @@
+use super::btrfs::dir_size;
+use super::{
+    DownPool, Error, InvalidDevice, Pool, PoolManager, Result, UpPool, Usage, Volume,
+    UMOUNT_ATTEMPTS,
+};
+use crate::storage::device::{Device, DeviceManager, Filesystem};
+use crate::system::Syscalls;
+use crate::Unit;
+use anyhow::Context;
+use std::io::ErrorKind;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+/// root mount path
+const MNT: &str = "/mnt";
+
+/// created by mkfs.ext4 on the root of the filesystem, it's not a volume
+const LOST_FOUND: &str = "lost+found";
+
+/// an ext4 volume is a plain directory on the root of the pool.
+pub struct Ext4Volume {
+    id: u64,
+    path: PathBuf,
+}
+
+impl Ext4Volume {
+    /// open the volume at path, the id of the volume is the inode
+    /// number of the directory
+    async fn open(path: PathBuf) -> Result<Self> {
+        let meta = match tokio::fs::metadata(&path).await {
+            Ok(meta) => meta,
+            Err(err) if err.kind() == ErrorKind::NotFound => {
+                return Err(Error::VolumeNotFound {
+                    volume: path
+                        .file_name()
+                        .map(|n| n.to_string_lossy().into())
+                        .unwrap_or_default(),
+                })
+            }
+            Err(err) => {
+                return Err(anyhow::Error::from(err)
+                    .context("failed to get volume information")
+                    .into())
+            }
+        };
+
+        if !meta.is_dir() {
+            return Err(Error::InvalidVolume { volume: path });
+        }
+
+        Ok(Self {
+            id: meta.ino(),
+            path,
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl Volume for Ext4Volume {
+    fn id(&self) -> u64 {
+        self.id
+    }
+
+    fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn name(&self) -> &str {
+        self.path
+            .file_name()
+            .map(|s| s.to_str().unwrap())
+            .unwrap_or("unknown")
+    }
+
+    async fn limit(&self, _size: Option<Unit>) -> Result<()> {
+        // ext4 directories has no size limit
+        Ok(())
+    }
+
+    async fn usage(&self) -> Result<Unit> {
+        Ok(dir_size(&self.path)
+            .await
+            .context("failed to calculate volume size")?)
+    }
+
+    async fn quota(&self) -> Result<Option<Unit>> {
+        Ok(None)
+    }
+
+    async fn rename<S: AsRef<str> + Send>(&self, new: S) -> Result<()> {
+        super::rename_volume(&self.path, new).await
+    }
+}
+
+pub struct Ext4DownPool<S, D>
+where
+    S: Syscalls,
+    D: Device,
+{
+    sys: S,
+    device: D,
+    root: PathBuf,
+}
+
+impl<S, D> Ext4DownPool<S, D>
+where
+    S: Syscalls + Send + Sync,
+    D: Device + Send + Sync,
+{
+    fn new(sys: S, device: D, root: PathBuf) -> Self {
+        Self { sys, device, root }
+    }
+
+    async fn up_impl(&self) -> Result<PathBuf> {
+        let path = self
+            .root
+            .join(self.device.label().ok_or_else(|| Error::InvalidDevice {
+                device: self.device.path().into(),
+                reason: InvalidDevice::InvalidLabel,
+            })?);
+
+        tokio::fs::create_dir_all(&path)
+            .await
+            .context("failed to create pool mount point")?;
+
+        crate::storage::mount::mount(
+            &self.sys,
+            Some(self.device.path()),
+            &path,
+            Some("ext4"),
+            nix::mount::MsFlags::empty(),
+            Option::<&str>::None,
+        )?;
+
+        Ok(path)
+    }
+}
+
+#[async_trait::async_trait]
+impl<S, D> DownPool for Ext4DownPool<S, D>
+where
+    S: Syscalls + Send + Sync,
+    D: Device + Send + Sync,
+{
+    type UpPool = Ext4UpPool<S, D>;
+
+    fn name(&self) -> &str {
+        // if we are at this state so device MUST have a label so it's safe to do this
+        self.device.label().unwrap()
+    }
+
+    fn size(&self) -> Unit {
+        self.device.size()
+    }
+
+    async fn up(self) -> std::result::Result<Self::UpPool, super::UpError<Self>> {
+        match self.up_impl().await {
+            Ok(path) => Ok(Ext4UpPool::new(self.sys, path, self.device, self.root)),
+            Err(err) => Err(super::UpError {
+                pool: self,
+                error: err,
+            }),
+        }
+    }
+}
+
+pub struct Ext4UpPool<S, D>
+where
+    S: Syscalls,
+    D: Device,
+{
+    sys: S,
+    device: D,
+    path: PathBuf,
+    root: PathBuf,
+}
+
+impl<S, D> Ext4UpPool<S, D>
+where
+    S: Syscalls + Send + Sync,
+    D: Device + Send + Sync,
+{
+    fn new(sys: S, path: PathBuf, device: D, root: PathBuf) -> Self {
+        Self {
+            sys,
+            device,
+            path,
+            root,
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl<S, D> UpPool for Ext4UpPool<S, D>
+where
+    S: Syscalls + Send + Sync,
+    D: Device + Send + Sync,
+{
+    type Volume = Ext4Volume;
+    type DownPool = Ext4DownPool<S, D>;
+
+    fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn name(&self) -> &str {
+        // if we are at this state so device MUST have a label so it's safe to do this
+        self.device.label().unwrap()
+    }
+
+    fn size(&self) -> Unit {
+        self.device.size()
+    }
+
+    async fn usage(&self) -> Result<Usage> {
+        let mut used: Unit = 0;
+        for volume in self.volumes().await? {
+            used += volume.usage().await?;
+        }
+
+        Ok(Usage {
+            size: self.device.size(),
+            used,
+        })
+    }
+
+    async fn down(self) -> std::result::Result<Self::DownPool, super::DownError<Self>> {
+        match crate::storage::mount::umount_retry(&self.sys, &self.path, UMOUNT_ATTEMPTS, false)
+            .await
+        {
+            Ok(_) => Ok(Ext4DownPool::new(self.sys, self.device, self.root)),
+            Err(err) => Err(super::DownError {
+                pool: self,
+                error: err.into(),
+            }),
+        }
+    }
+
+    async fn volumes(&self) -> Result<Vec<Self::Volume>> {
+        let mut volumes = vec![];
+        let mut entries = tokio::fs::read_dir(&self.path)
+            .await
+            .context("failed to list pool volumes")?;
+
+        while let Some(entry) = entries
+            .next_entry()
+            .await
+            .context("failed to list pool volumes")?
+        {
+            if entry.file_name() == LOST_FOUND {
+                continue;
+            }
+
+            let meta = entry
+                .metadata()
+                .await
+                .context("failed to get volume information")?;
+
+            if !meta.is_dir() {
+                continue;
+            }
+
+            volumes.push(Ext4Volume {
+                id: meta.ino(),
+                path: entry.path(),
+            });
+        }
+
+        Ok(volumes)
+    }
+
+    async fn volume_create<N: AsRef<str> + Send>(&self, name: N) -> Result<Self::Volume> {
+        let name = name.as_ref();
+        let path = self.path.join(name);
+        match tokio::fs::create_dir(&path).await {
+            Ok(_) => (),
+            Err(err) if err.kind() == ErrorKind::AlreadyExists => {
+                return Err(Error::VolumeAlreadyExists {
+                    volume: name.into(),
+                })
+            }
+            Err(err) => {
+                return Err(anyhow::Error::from(err)
+                    .context("failed to create volume")
+                    .into())
+            }
+        };
+
+        Ext4Volume::open(path).await
+    }
+
+    async fn volume_delete<N: AsRef<str> + Send>(&self, name: N) -> Result<()> {
+        let volume = self.volume(name.as_ref()).await?;
+        tokio::fs::remove_dir_all(volume.path())
+            .await
+            .context("failed to delete volume")?;
+
+        Ok(())
+    }
+
+    async fn volume<N: AsRef<str> + Send + Sync>(&self, name: N) -> Result<Self::Volume> {
+        if name.as_ref() == LOST_FOUND {
+            return Err(Error::VolumeNotFound {
+                volume: name.as_ref().into(),
+            });
+        }
+
+        Ext4Volume::open(self.path.join(name.as_ref())).await
+    }
+}
+
+/// shorthand for an ext4 pool
+pub type Ext4Pool<S, D> = Pool<Ext4UpPool<S, D>, Ext4DownPool<S, D>>;
+
+impl<S, D> Ext4Pool<S, D>
+where
+    S: Syscalls + Send + Sync,
+    D: Device + Send + Sync,
+{
+    /// create a new ext4 pool from device. the device must have a valid
+    /// ext4 filesystem. root is where the pool is mounted when brought up
+    async fn with<R: Into<PathBuf>>(sys: S, device: D, root: R) -> Result<Self> {
+        let path = device.path().to_str().ok_or_else(|| Error::InvalidDevice {
+            device: device.path().into(),
+            reason: InvalidDevice::InvalidPath,
+        })?;
+
+        if device.filesystem().is_none() || device.label().is_none() {
+            return Err(Error::InvalidFilesystem {
+                device: device.path().into(),
+            });
+        }
+
+        let mnt = crate::storage::mountinfo(path).await?.into_iter().next();
+
+        match mnt {
+            Some(mnt) => Ok(Ext4Pool::Up(Ext4UpPool::new(
+                sys,
+                mnt.target,
+                device,
+                root.into(),
+            ))),
+            None => Ok(Ext4Pool::Down(Ext4DownPool::new(sys, device, root.into()))),
+        }
+    }
+}
+
+pub struct Ext4Manager<S>
+where
+    S: Syscalls + Clone,
+{
+    sys: S,
+}
+
+impl<S> Ext4Manager<S>
+where
+    S: Syscalls + Clone + Send + Sync,
+{
+    pub fn new(sys: S) -> Self {
+        Self { sys }
+    }
+}
+
+#[async_trait::async_trait]
+impl<S, M> PoolManager<M, Ext4UpPool<S, M::Device>, Ext4DownPool<S, M::Device>> for Ext4Manager<S>
+where
+    S: Syscalls + Clone + Send + Sync,
+    M: DeviceManager + Send + Sync + 'static,
+{
+    async fn get(&self, manager: &M, device: M::Device) -> Result<Ext4Pool<S, M::Device>> {
+        let device = match device.filesystem() {
+            None => manager
+                .format(device, Filesystem::Ext4, false)
+                .await
+                .context("failed to prepare filesystem")?,
+            Some("ext4") => {
+                if device.label().is_some() {
+                    device
+                } else {
+                    return Err(Error::InvalidDevice {
+                        device: device.path().into(),
+                        reason: InvalidDevice::InvalidLabel,
+                    });
+                }
+            }
+            _ => {
+                return Err(Error::InvalidFilesystem {
+                    device: device.path().into(),
+                })
+            }
+        };
+
+        Ext4Pool::with(self.sys.clone(), device, MNT).await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{DownPool, Ext4Pool, Pool, UpPool, Volume};
+    use crate::storage::device::Device;
+    use crate::storage::pool::Error;
+    use crate::system::Syscalls;
+    use crate::Unit;
+    use anyhow::Result;
+    use std::path::{Path, PathBuf};
+
+    // mock syscall always succeed
+    struct MockSyscalls;
+    impl Syscalls for MockSyscalls {
+        fn mount<S: AsRef<Path>, T: AsRef<Path>, F: AsRef<str>, D: AsRef<str>>(
+            &self,
+            _source: Option<S>,
+            _target: T,
+            _fstype: Option<F>,
+            _flags: nix::mount::MsFlags,
+            _data: Option<D>,
+        ) -> Result<(), crate::system::Error> {
+            Ok(())
+        }
+
+        fn umount<T: AsRef<Path>>(
+            &self,
+            _target: T,
+            _flags: Option<nix::mount::MntFlags>,
+        ) -> Result<(), crate::system::Error> {
+            Ok(())
+        }
+    }
+
+    struct MockDevice {
+        path: PathBuf,
+        size: Unit,
+        label: String,
+    }
+
+    impl Device for MockDevice {
+        fn path(&self) -> &Path {
+            &self.path
+        }
+
+        fn size(&self) -> Unit {
+            self.size
+        }
+
+        fn subsystems(&self) -> &str {
+            "mock:device"
+        }
+
+        fn filesystem(&self) -> Option<&str> {
+            Some("ext4")
+        }
+
+        fn label(&self) -> Option<&str> {
+            Some(&self.label)
+        }
+
+        fn rota(&self) -> bool {
+            true
+        }
+    }
+
+    #[tokio::test]
+    async fn pool_new() {
+        let root = tempfile::tempdir().unwrap();
+        let device = MockDevice {
+            path: "/dev/mock".into(),
+            size: 100 * crate::GIGABYTE,
+            label: "test-device".into(),
+        };
+
+        let pool = Ext4Pool::with(MockSyscalls, device, root.path())
+            .await
+            .unwrap();
+        // because device is NOT (and will never be) mounted. it means pool returned in the mock is always in Down state
+        let pool = match pool {
+            Pool::Down(pool) => pool,
+            _ => panic!("invalid pool type returned"),
+        };
+
+        let up = pool.up().await.unwrap();
+
+        assert_eq!(up.name(), "test-device");
+        assert_eq!(up.path(), root.path().join("test-device"));
+
+        // mkfs.ext4 always creates lost+found
+        tokio::fs::create_dir(up.path().join("lost+found"))
+            .await
+            .unwrap();
+
+        let volumes = up.volumes().await.unwrap();
+        assert_eq!(volumes.len(), 0);
+
+        let cache = up.volume_create("zos-cache").await.unwrap();
+        assert_eq!(cache.name(), "zos-cache");
+        assert_eq!(cache.path(), root.path().join("test-device/zos-cache"));
+        cache.limit(Some(100 * crate::GIGABYTE)).await.unwrap();
+
+        tokio::fs::write(cache.path().join("data"), vec![0; 1024])
+            .await
+            .unwrap();
+
+        let exists = up.volume_create("zos-cache").await;
+        assert!(
+            matches!(exists, Err(Error::VolumeAlreadyExists { volume }) if volume == "zos-cache")
+        );
+
+        let volumes = up.volumes().await.unwrap();
+        assert_eq!(volumes.len(), 1);
+        assert_eq!(volumes[0].id(), cache.id());
+
+        let usage = up.usage().await.unwrap();
+        assert_eq!(usage.size, 100 * crate::GIGABYTE);
+        assert_eq!(usage.used, 1024);
+
+        cache.rename("zos-cache-old").await.unwrap();
+        let vol = up.volume("zos-cache").await;
+        assert!(matches!(vol, Err(Error::VolumeNotFound { volume }) if volume == "zos-cache"));
+
+        let cache = up.volume("zos-cache-old").await.unwrap();
+        assert_eq!(cache.usage().await.unwrap(), 1024);
+
+        up.volume_create("zos-cache").await.unwrap();
+        let exists = cache.rename("zos-cache").await;
+        assert!(
+            matches!(exists, Err(Error::VolumeAlreadyExists { volume }) if volume == "zos-cache")
+        );
+
+        up.volume_delete("zos-cache").await.unwrap();
+        up.volume_delete("zos-cache-old").await.unwrap();
+        let vol = up.volume("zos-cache").await;
+        assert!(matches!(vol, Err(Error::VolumeNotFound { volume }) if volume == "zos-cache"));
+
+        let pool = up.down().await.unwrap();
+        assert_eq!(pool.name(), "test-device");
+    }
+}
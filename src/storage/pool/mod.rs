@@ -1,6 +1,7 @@
 /// a pool is a wrapper around a disk device. right now a single pool
 /// uses a single disk device.
 use crate::Unit;
+use anyhow::Context;
 use std::fmt::{Debug, Display};
 use std::path::{Path, PathBuf};
 use thiserror::Error;
@@ -9,7 +10,12 @@ use super::device::DeviceManager;
 pub use crate::storage::Usage;
 
 pub mod btrfs;
+pub mod ext4;
 pub use btrfs::BtrfsManager;
+pub use ext4::Ext4Manager;
+
+// how many times to try unmounting a busy pool
+const UMOUNT_ATTEMPTS: usize = 3;
 
 #[derive(Debug)]
 pub enum InvalidDevice {
@@ -148,6 +154,39 @@ pub trait Volume: Send + Sync {
     /// set the actual disk usage (by files in the volume) is
     /// returned
     async fn usage(&self) -> Result<Unit>;
+
+    /// quota returns the size limit set on the volume, None
+    /// if the volume is not limited
+    async fn quota(&self) -> Result<Option<Unit>>;
+
+    /// rename the volume inside the same pool. the volume object
+    /// is not updated, the volume need to be looked up again
+    /// with the new name.
+    async fn rename<S: AsRef<str> + Send>(&self, new: S) -> Result<()>;
+}
+
+/// rename a volume directory (or subvolume) to new name in the same
+/// parent directory.
+async fn rename_volume<S: AsRef<str>>(path: &Path, new: S) -> Result<()> {
+    let new = new.as_ref();
+    let target = match path.parent() {
+        Some(parent) => parent.join(new),
+        None => {
+            return Err(Error::InvalidVolume {
+                volume: path.into(),
+            })
+        }
+    };
+
+    if tokio::fs::symlink_metadata(&target).await.is_ok() {
+        return Err(Error::VolumeAlreadyExists { volume: new.into() });
+    }
+
+    tokio::fs::rename(path, &target)
+        .await
+        .with_context(|| format!("failed to rename volume {:?} to {:?}", path, target))?;
+
+    Ok(())
 }
 
 /// UpPool is trait for a pool that is hooked to the system and accessible
@@ -183,6 +222,12 @@ pub trait UpPool: Sized + Send + Sync {
     async fn volume_delete<S: AsRef<str> + Send>(&self, name: S) -> Result<()>;
 
     async fn volume<S: AsRef<str> + Send + Sync>(&self, name: S) -> Result<Self::Volume>;
+
+    /// maintenance runs the filesystem housekeeping (if any) to reclaim
+    /// space and reduce fragmentation. by default it does nothing
+    async fn maintenance(&self) -> Result<()> {
+        Ok(())
+    }
 }
 
 #[async_trait::async_trait]
@@ -196,7 +241,7 @@ pub trait DownPool: Sized + Send + Sync {
     fn size(&self) -> Unit;
 }
 
-#[derive(Debug, PartialEq, Eq, Clone)]
+#[derive(Debug, PartialEq, Eq, Clone, serde::Serialize, serde::Deserialize)]
 pub enum State {
     Up,
     Down,
@@ -1,7 +1,11 @@
+use futures::Stream;
+pub use nix::errno::Errno;
 pub use nix::mount::{MntFlags, MsFlags};
 use std::ffi::OsString;
 use std::fmt::Display;
 use std::path::Path;
+use std::pin::Pin;
+use std::time::Duration;
 use thiserror::Error;
 use tokio::process::Command as TokioCommand;
 
@@ -10,6 +14,7 @@ pub enum Error {
     Spawn(#[from] std::io::Error),
     Exit { code: i32, stderr: Vec<u8> },
     Unix(#[from] nix::Error),
+    Timeout { timeout: Duration },
 }
 
 impl Error {
@@ -25,6 +30,31 @@ impl Error {
             },
         }
     }
+
+    /// errno of a failed syscall (like mount or umount), or of an io
+    /// error that carries an os error code
+    pub fn errno(&self) -> Option<Errno> {
+        match self {
+            Error::Unix(errno) => Some(*errno),
+            Error::Spawn(err) => err.raw_os_error().map(Errno::from_i32),
+            _ => None,
+        }
+    }
+
+    /// resource is busy (EBUSY), usually worth a retry
+    pub fn is_busy(&self) -> bool {
+        self.errno() == Some(Errno::EBUSY)
+    }
+
+    /// file or directory does not exist (ENOENT)
+    pub fn is_not_found(&self) -> bool {
+        self.errno() == Some(Errno::ENOENT)
+    }
+
+    /// operation is not allowed (EACCES or EPERM)
+    pub fn is_permission_denied(&self) -> bool {
+        matches!(self.errno(), Some(Errno::EACCES) | Some(Errno::EPERM))
+    }
 }
 
 impl Display for Error {
@@ -34,8 +64,8 @@ impl Display for Error {
                 ref code,
                 ref stderr,
             } => {
-                let msg = String::from_utf8(stderr.clone()).map_err(|_| std::fmt::Error)?;
-                write!(f, "error-code: {} - message: {}", code, msg)
+                let msg = String::from_utf8_lossy(stderr);
+                write!(f, "error-code: {} - message: {}", code, msg.trim())
             }
             Error::Spawn(ref err) => {
                 write!(f, "failed to spawn command: {}", err)
@@ -43,14 +73,18 @@ impl Display for Error {
             Error::Unix(ref err) => {
                 write!(f, "{}", err)
             }
+            Error::Timeout { ref timeout } => {
+                write!(f, "command timed out after {:?}", timeout)
+            }
         }
     }
 }
 
-#[derive(Debug, Eq, PartialEq)]
+#[derive(Debug, Clone, Eq, PartialEq)]
 pub struct Command {
     cmd: OsString,
     args: Vec<OsString>,
+    stdin: Option<Vec<u8>>,
 }
 
 impl Command {
@@ -59,6 +93,7 @@ impl Command {
         Command {
             cmd: cmd.into(),
             args: Vec::default(),
+            stdin: None,
         }
     }
 
@@ -67,6 +102,17 @@ impl Command {
         self.args.push(arg.into());
         self
     }
+
+    /// set data to be written to the command stdin
+    pub fn stdin(mut self, data: Vec<u8>) -> Self {
+        self.stdin = Some(data);
+        self
+    }
+
+    /// data that will be written to the command stdin if set
+    pub fn input(&self) -> Option<&[u8]> {
+        self.stdin.as_deref()
+    }
 }
 
 impl Display for Command {
@@ -91,6 +137,10 @@ impl From<&Command> for TokioCommand {
     }
 }
 
+/// stream of stdout chunks of a running command. If the command
+/// fails, the last item is the exit error.
+pub type OutputStream = Pin<Box<dyn Stream<Item = Result<Vec<u8>, Error>> + Send>>;
+
 #[cfg_attr(test, mockall::automock)]
 #[async_trait::async_trait]
 pub trait Executor {
@@ -99,6 +149,74 @@ pub trait Executor {
     /// that are expect to return a lot of output since all output
     /// is captured.
     async fn run(&self, cmd: &Command) -> Result<Vec<u8>, Error>;
+
+    /// same as run but the command is killed if it does not exit
+    /// within timeout. Executors that can't enforce a timeout
+    /// just run the command.
+    async fn run_timeout(&self, cmd: &Command, _timeout: Duration) -> Result<Vec<u8>, Error> {
+        self.run(cmd).await
+    }
+
+    /// run_stream runs a (possibly long running) command and return a stream of
+    /// its output chunks as they arrive. Executors that can't stream the output
+    /// yield the full output once the command exits.
+    async fn run_stream(&self, cmd: &Command) -> Result<OutputStream, Error> {
+        let out = self.run(cmd).await?;
+        Ok(Box::pin(futures::stream::once(async move { Ok(out) })))
+    }
+}
+
+/// RecordingExecutor records every command it's asked to run and answers
+/// them in order from a queue of canned responses. Once the queue is empty
+/// commands succeed with an empty output. Clones share the same records so
+/// a test can keep a clone to check the commands after moving the executor.
+#[cfg(test)]
+#[derive(Default, Clone)]
+pub struct RecordingExecutor {
+    inner: std::sync::Arc<std::sync::Mutex<Recording>>,
+}
+
+#[cfg(test)]
+#[derive(Default)]
+struct Recording {
+    commands: Vec<Command>,
+    responses: std::collections::VecDeque<Result<Vec<u8>, Error>>,
+}
+
+#[cfg(test)]
+impl RecordingExecutor {
+    /// queue a successful response with given output
+    pub fn respond<O: Into<Vec<u8>>>(self, output: O) -> Self {
+        self.push(Ok(output.into()))
+    }
+
+    /// queue a failed response
+    pub fn fail(self, err: Error) -> Self {
+        self.push(Err(err))
+    }
+
+    fn push(self, response: Result<Vec<u8>, Error>) -> Self {
+        self.inner.lock().unwrap().responses.push_back(response);
+        self
+    }
+
+    /// all commands run so far
+    pub fn commands(&self) -> Vec<Command> {
+        self.inner.lock().unwrap().commands.clone()
+    }
+}
+
+#[cfg(test)]
+#[async_trait::async_trait]
+impl Executor for RecordingExecutor {
+    async fn run(&self, cmd: &Command) -> Result<Vec<u8>, Error> {
+        let mut inner = self.inner.lock().unwrap();
+        inner.commands.push(cmd.clone());
+        inner
+            .responses
+            .pop_front()
+            .unwrap_or_else(|| Ok(Vec::default()))
+    }
 }
 
 /// Syscalls trait to help with testing operations that requires calls
@@ -118,21 +236,103 @@ pub trait Syscalls {
     fn umount<T: AsRef<Path>>(&self, target: T, flags: Option<MntFlags>) -> Result<(), Error>;
 }
 
+// delay between umount attempts on a busy mount point
+const UMOUNT_RETRY_DELAY: Duration = Duration::from_millis(500);
+
+/// umount_retry unmounts target, retrying up to `attempts` times (at least once)
+/// while the mount point is busy (EBUSY). If `detach` is set, the last attempt
+/// is a lazy unmount (MNT_DETACH) which always succeeds on a busy mount point
+/// but the filesystem is only released once it's not in use anymore.
+pub async fn umount_retry<S: Syscalls, T: AsRef<Path>>(
+    sys: &S,
+    target: T,
+    attempts: usize,
+    detach: bool,
+) -> Result<(), Error> {
+    let target = target.as_ref();
+    let attempts = attempts.max(1);
+    for attempt in 1..=attempts {
+        let flags = if detach && attempt == attempts {
+            Some(MntFlags::MNT_DETACH)
+        } else {
+            None
+        };
+
+        match sys.umount(target, flags) {
+            Err(err) if err.is_busy() && attempt < attempts => {
+                log::debug!("mount {:?} is busy, retrying", target);
+                tokio::time::sleep(UMOUNT_RETRY_DELAY).await;
+            }
+            result => return result,
+        }
+    }
+
+    unreachable!()
+}
+
 #[derive(Default, Clone)]
 /// System is the default executor
 /// that uses the tokio::process module
 /// to implement the executor trait.
 pub struct System;
 
-#[async_trait::async_trait]
-impl Executor for System {
-    async fn run(&self, cmd: &Command) -> Result<Vec<u8>, Error> {
+impl System {
+    async fn output(cmd: &Command) -> Result<std::process::Output, Error> {
+        let input = cmd.input();
         let mut cmd: TokioCommand = cmd.into();
-        let out = cmd.output().await?;
+        // make sure the child does not outlive the future (on timeout)
+        cmd.kill_on_drop(true);
+        let out = match input {
+            None => cmd.output().await?,
+            Some(input) => {
+                use std::process::Stdio;
+                use tokio::io::AsyncWriteExt;
+
+                let mut child = cmd
+                    .stdin(Stdio::piped())
+                    .stdout(Stdio::piped())
+                    .stderr(Stdio::piped())
+                    .spawn()?;
+
+                // stdin is written while the output is collected otherwise
+                // the child can block on a full stdout pipe.
+                let mut stdin = child.stdin.take();
+                let write = async move {
+                    if let Some(ref mut stdin) = stdin {
+                        stdin.write_all(input).await?;
+                    }
+                    // dropping stdin closes it so the child sees EOF
+                    Ok::<_, std::io::Error>(())
+                };
+
+                let (write, out) = tokio::join!(write, child.wait_with_output());
+                let out = out?;
+                // a child that exits without reading all input causes a broken pipe,
+                // the exit status is more relevant in that case.
+                match write {
+                    Err(err) if err.kind() != std::io::ErrorKind::BrokenPipe => {
+                        return Err(err.into())
+                    }
+                    _ => out,
+                }
+            }
+        };
+
+        Ok(out)
+    }
+
+    async fn exec(cmd: &Command, timeout: Option<Duration>) -> Result<Vec<u8>, Error> {
+        let out = match timeout {
+            None => Self::output(cmd).await?,
+            Some(timeout) => tokio::time::timeout(timeout, Self::output(cmd))
+                .await
+                .map_err(|_| Error::Timeout { timeout })??,
+        };
+
         if !out.status.success() {
             return Err(Error::Exit {
                 code: out.status.code().unwrap_or(512),
-                stderr: out.stderr,
+                stderr: String::from_utf8_lossy(&out.stderr).trim().into(),
             });
         }
 
@@ -140,6 +340,99 @@ impl Executor for System {
     }
 }
 
+struct StreamState {
+    child: tokio::process::Child,
+    stdout: tokio::process::ChildStdout,
+    stderr: tokio::task::JoinHandle<std::io::Result<Vec<u8>>>,
+}
+
+#[async_trait::async_trait]
+impl Executor for System {
+    async fn run(&self, cmd: &Command) -> Result<Vec<u8>, Error> {
+        Self::exec(cmd, None).await
+    }
+
+    async fn run_timeout(&self, cmd: &Command, timeout: Duration) -> Result<Vec<u8>, Error> {
+        Self::exec(cmd, Some(timeout)).await
+    }
+
+    async fn run_stream(&self, cmd: &Command) -> Result<OutputStream, Error> {
+        use std::process::Stdio;
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let input = cmd.input().map(Vec::from);
+        let mut cmd: TokioCommand = cmd.into();
+        let mut child = cmd
+            .kill_on_drop(true)
+            .stdin(if input.is_some() {
+                Stdio::piped()
+            } else {
+                Stdio::null()
+            })
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+
+        if let (Some(mut stdin), Some(input)) = (child.stdin.take(), input) {
+            tokio::spawn(async move {
+                // errors here means the child exited (or closed stdin)
+                let _ = stdin.write_all(&input).await;
+            });
+        }
+
+        // stderr need to be drained while reading stdout otherwise
+        // the child can block on a full stderr pipe.
+        let mut stderr = child.stderr.take().expect("stderr is piped");
+        let stderr = tokio::spawn(async move {
+            let mut buf = Vec::default();
+            stderr.read_to_end(&mut buf).await?;
+            Ok(buf)
+        });
+
+        let stdout = child.stdout.take().expect("stdout is piped");
+        let state = StreamState {
+            child,
+            stdout,
+            stderr,
+        };
+
+        let stream = futures::stream::unfold(Some(state), |state| async move {
+            let mut state = state?;
+            let mut buf = vec![0; 4096];
+            match state.stdout.read(&mut buf).await {
+                Ok(0) => {
+                    let status = match state.child.wait().await {
+                        Ok(status) => status,
+                        Err(err) => return Some((Err(err.into()), None)),
+                    };
+
+                    if status.success() {
+                        return None;
+                    }
+
+                    let stderr = match state.stderr.await {
+                        Ok(Ok(stderr)) => stderr,
+                        _ => Vec::default(),
+                    };
+
+                    let err = Error::Exit {
+                        code: status.code().unwrap_or(512),
+                        stderr: String::from_utf8_lossy(&stderr).trim().into(),
+                    };
+                    Some((Err(err), None))
+                }
+                Ok(n) => {
+                    buf.truncate(n);
+                    Some((Ok(buf), Some(state)))
+                }
+                Err(err) => Some((Err(err.into()), None)),
+            }
+        });
+
+        Ok(Box::pin(stream))
+    }
+}
+
 impl Syscalls for System {
     fn mount<S: AsRef<Path>, T: AsRef<Path>, F: AsRef<str>, D: AsRef<str>>(
         &self,
@@ -172,7 +465,11 @@ impl Syscalls for System {
 
 #[cfg(test)]
 mod test {
-    use super::{Command, Error, Executor, System};
+    use super::{
+        Command, Errno, Error, Executor, MntFlags, MsFlags, RecordingExecutor, Syscalls, System,
+    };
+    use std::path::Path;
+    use std::sync::Mutex;
 
     #[tokio::test]
     async fn system_run_success() {
@@ -198,7 +495,209 @@ mod test {
         let out = System.run(&cmd).await;
 
         assert!(
-            matches!(out, Err(Error::Exit{code, stderr}) if code == 2 && String::from_utf8_lossy(&stderr) == "bye world\n")
+            matches!(out, Err(Error::Exit{code, stderr}) if code == 2 && String::from_utf8_lossy(&stderr) == "bye world")
+        );
+    }
+
+    #[tokio::test]
+    async fn system_run_failure_display() {
+        let cmd = Command::new("sh").arg("-c").arg(
+            "echo '  ERROR: cannot access /mnt/pool: No such file or directory' 1>&2 && exit 1",
+        );
+
+        let err = System.run(&cmd).await.unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "error-code: 1 - message: ERROR: cannot access /mnt/pool: No such file or directory"
+        );
+
+        // invalid utf8 in stderr should not fail formatting
+        let err = Error::Exit {
+            code: 3,
+            stderr: vec![0xff, b'o', b'k', b'\n'],
+        };
+        assert_eq!(err.to_string(), "error-code: 3 - message: \u{fffd}ok");
+    }
+
+    #[tokio::test]
+    async fn system_run_stdin() {
+        let cmd = Command::new("cat").stdin("hello world".into());
+        assert!(matches!(cmd.input(), Some(input) if input == b"hello world"));
+
+        let out = System.run(&cmd).await.unwrap();
+        assert!(String::from_utf8_lossy(&out) == "hello world");
+    }
+
+    #[tokio::test]
+    async fn system_run_timeout() {
+        use std::time::{Duration, Instant};
+
+        let cmd = Command::new("sleep").arg("5");
+        let start = Instant::now();
+        let out = System.run_timeout(&cmd, Duration::from_millis(100)).await;
+
+        assert!(
+            matches!(out, Err(Error::Timeout { timeout }) if timeout == Duration::from_millis(100))
+        );
+        assert!(start.elapsed() < Duration::from_secs(2));
+
+        let cmd = Command::new("echo").arg("hello world");
+        let out = System
+            .run_timeout(&cmd, Duration::from_secs(5))
+            .await
+            .unwrap();
+        assert!(String::from_utf8_lossy(&out) == "hello world\n");
+    }
+
+    #[tokio::test]
+    async fn system_run_stream() {
+        use futures::StreamExt;
+        use std::time::{Duration, Instant};
+
+        let cmd = Command::new("sh")
+            .arg("-c")
+            .arg("for i in 1 2 3; do echo line$i; sleep 0.3; done");
+
+        let start = Instant::now();
+        let mut stream = System.run_stream(&cmd).await.unwrap();
+        let mut chunks = vec![];
+        while let Some(chunk) = stream.next().await {
+            chunks.push((start.elapsed(), chunk.unwrap()));
+        }
+
+        // each line is received on its own before the command exits
+        assert_eq!(chunks.len(), 3);
+        assert!(chunks[0].0 < Duration::from_millis(300));
+        assert!(chunks[2].0 >= Duration::from_millis(600));
+
+        let output: Vec<u8> = chunks.into_iter().flat_map(|(_, c)| c).collect();
+        assert_eq!(String::from_utf8_lossy(&output), "line1\nline2\nline3\n");
+    }
+
+    #[tokio::test]
+    async fn system_run_stream_failure() {
+        use futures::StreamExt;
+
+        let cmd = Command::new("sh")
+            .arg("-c")
+            .arg("echo hello && echo 'bye world' 1>&2 && exit 2");
+
+        let stream = System.run_stream(&cmd).await.unwrap();
+        let items: Vec<_> = stream.collect().await;
+
+        assert_eq!(items.len(), 2);
+        assert!(matches!(&items[0], Ok(out) if out == b"hello\n"));
+        assert!(
+            matches!(&items[1], Err(Error::Exit{code, stderr}) if *code == 2 && stderr == b"bye world")
+        );
+    }
+
+    #[tokio::test]
+    async fn recording_executor() {
+        let exec = RecordingExecutor::default()
+            .respond("first")
+            .fail(Error::new(1, Some("second")));
+
+        let first = Command::new("echo").arg("1");
+        let second = Command::new("echo").arg("2");
+        let third = Command::new("echo").arg("3");
+
+        assert_eq!(exec.run(&first).await.unwrap(), b"first");
+        assert!(matches!(
+            exec.run(&second).await,
+            Err(Error::Exit { code: 1, .. })
+        ));
+        // out of responses
+        assert!(exec.clone().run(&third).await.unwrap().is_empty());
+
+        assert_eq!(exec.commands(), vec![first, second, third]);
+    }
+
+    #[test]
+    fn error_errno() {
+        let err = Error::from(Errno::EBUSY);
+        assert_eq!(err.errno(), Some(Errno::EBUSY));
+        assert!(err.is_busy());
+        assert!(!err.is_not_found());
+
+        let err = Error::from(Errno::ENOENT);
+        assert!(err.is_not_found());
+        assert!(!err.is_busy());
+
+        assert!(Error::from(Errno::EACCES).is_permission_denied());
+        assert!(Error::from(Errno::EPERM).is_permission_denied());
+
+        // io errors with an os code map to the same errno
+        let err = Error::from(std::io::Error::from_raw_os_error(Errno::ENOENT as i32));
+        assert!(err.is_not_found());
+
+        // command failures have no errno
+        let err = Error::new(16, Some("device or resource busy"));
+        assert_eq!(err.errno(), None);
+        assert!(!err.is_busy());
+    }
+
+    // syscalls that fail umount with EBUSY `busy` times
+    struct BusySyscalls {
+        busy: usize,
+        calls: Mutex<Vec<Option<MntFlags>>>,
+    }
+
+    impl Syscalls for BusySyscalls {
+        fn mount<S: AsRef<Path>, T: AsRef<Path>, F: AsRef<str>, D: AsRef<str>>(
+            &self,
+            _source: Option<S>,
+            _target: T,
+            _fstype: Option<F>,
+            _flags: MsFlags,
+            _data: Option<D>,
+        ) -> Result<(), Error> {
+            unimplemented!()
+        }
+
+        fn umount<T: AsRef<Path>>(&self, _target: T, flags: Option<MntFlags>) -> Result<(), Error> {
+            let mut calls = self.calls.lock().unwrap();
+            calls.push(flags);
+            if calls.len() <= self.busy && flags.is_none() {
+                return Err(Error::Unix(nix::Error::EBUSY));
+            }
+            Ok(())
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn umount_retry() {
+        let sys = BusySyscalls {
+            busy: 2,
+            calls: Mutex::default(),
+        };
+        super::umount_retry(&sys, "/mnt/test", 5, false)
+            .await
+            .expect("umount should succeed on third attempt");
+        assert_eq!(*sys.calls.lock().unwrap(), vec![None, None, None]);
+
+        // still busy after all attempts
+        let sys = BusySyscalls {
+            busy: 5,
+            calls: Mutex::default(),
+        };
+        let result = super::umount_retry(&sys, "/mnt/test", 3, false).await;
+        assert!(matches!(result, Err(Error::Unix(nix::Error::EBUSY))));
+        assert_eq!(sys.calls.lock().unwrap().len(), 3);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn umount_retry_detach() {
+        let sys = BusySyscalls {
+            busy: 5,
+            calls: Mutex::default(),
+        };
+        super::umount_retry(&sys, "/mnt/test", 3, true)
+            .await
+            .expect("lazy umount should succeed");
+        assert_eq!(
+            *sys.calls.lock().unwrap(),
+            vec![None, None, Some(MntFlags::MNT_DETACH)]
         );
     }
 }
@@ -1,11 +1,15 @@
 use anyhow::Context;
-use semver::Version;
+use semver::{Version, VersionReq};
+use serde::{de::DeserializeOwned, Serialize};
+use std::ffi::OsString;
 use std::fs::Permissions;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
 use std::str::{self, FromStr};
+use std::task::{Context as TaskContext, Poll};
 use std::{fmt::Debug, os::unix::prelude::PermissionsExt};
 use thiserror::Error;
-use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadBuf};
 
 /// Maximum allowed version length.
 const MAX_VERSION_LENGTH: u8 = 50;
@@ -24,62 +28,105 @@ pub enum Error {
     #[error("max version length is {}", MAX_VERSION_LENGTH)]
     VersionLengthExceeded,
 
+    /// NoMigrationPath error is raised if data can't be migrated to the latest version
+    #[error("no migration path from version {version}")]
+    NoMigrationPath { version: Version },
+
     #[error("{0}")]
     IO(#[from] std::io::Error),
 
+    /// Serde error is raised if an object failed to encode or decode
+    #[error("{0}")]
+    Serde(#[from] serde_json::Error),
+
     #[error("{0}")]
     Other(#[from] anyhow::Error),
 }
 
 pub type Result<T> = std::result::Result<T, Error>;
 
-/// Creates a new versioned reader from a stream. It fails
-/// if the reader can not read the version from the stream.
-/// On success, the reader will have a version, and then can be used
-/// to load the data.
-///
-/// The reader should be a type implementing [`AsyncRead`] + [`Unpin`].
-///
-/// If parsing succeeds, returns `VersionedReader<R>` inside [`Ok`].
-///
-/// # Errors
-/// Returns `Err` if version information is not found or valid, or when there is an io error.
-///
-/// # Example
-/// ```ignore
-/// let mut file = tokio::fs::OpenOptions::new()
-///     .read(true)
-///     .open(path.as_ref())
-///     .await?;
-/// let mut reader = VersionedReader::new(&mut file).await?;
-/// ```
-pub async fn reader<R: AsyncRead + Unpin>(mut r: R) -> Result<(Version, R)> {
-    let mut double_quotes: u8 = 0;
-    let mut version_bytes = Vec::<u8>::new();
-    for _ in 0..MAX_VERSION_LENGTH {
-        let byte = r.read_u8().await?;
-        if double_quotes == 0 && byte != b'\"' {
-            return Err(Error::NotVersioned);
-        }
-        if byte == b'\"' {
-            double_quotes += 1;
-            if double_quotes == 2 {
-                break;
+/// VersionedReader wraps a reader positioned right after the version
+/// prefix. It carries the parsed [`Version`] and implements [`AsyncRead`]
+/// so it can be passed to code that only consumes the data.
+pub struct VersionedReader<R> {
+    version: Version,
+    inner: R,
+}
+
+impl<R: AsyncRead + Unpin> VersionedReader<R> {
+    /// Creates a new versioned reader from a stream. It fails
+    /// if the reader can not read the version from the stream.
+    /// On success, the reader will have a version, and then can be used
+    /// to load the data.
+    ///
+    /// The reader should be a type implementing [`AsyncRead`] + [`Unpin`].
+    ///
+    /// # Errors
+    /// Returns `Err` if version information is not found or valid, or when there is an io error.
+    ///
+    /// # Example
+    /// ```ignore
+    /// let mut file = tokio::fs::OpenOptions::new()
+    ///     .read(true)
+    ///     .open(path.as_ref())
+    ///     .await?;
+    /// let mut reader = VersionedReader::new(&mut file).await?;
+    /// ```
+    pub async fn new(mut r: R) -> Result<Self> {
+        let mut double_quotes: u8 = 0;
+        let mut version_bytes = Vec::<u8>::new();
+        for _ in 0..MAX_VERSION_LENGTH {
+            let byte = r.read_u8().await?;
+            if double_quotes == 0 && byte != b'\"' {
+                return Err(Error::NotVersioned);
             }
-            continue;
+            if byte == b'\"' {
+                double_quotes += 1;
+                if double_quotes == 2 {
+                    break;
+                }
+                continue;
+            }
+            version_bytes.push(byte);
+        }
+        if double_quotes != 2 {
+            return Err(Error::VersionLengthExceeded);
         }
-        version_bytes.push(byte);
+        let version_str = str::from_utf8(&version_bytes)
+            .context("failed to convert version information to string")?;
+        let version = Version::from_str(version_str).map_err(|_| Error::InvalidVersion {
+            version: version_str.into(),
+        })?;
+
+        Ok(VersionedReader { version, inner: r })
     }
-    if double_quotes != 2 {
-        return Err(Error::VersionLengthExceeded);
+}
+
+impl<R> VersionedReader<R> {
+    /// version of the data
+    pub fn version(&self) -> &Version {
+        &self.version
     }
-    let version_str = str::from_utf8(&version_bytes)
-        .context("failed to convert version information to string")?;
-    let version = Version::from_str(version_str).map_err(|_| Error::InvalidVersion {
-        version: version_str.into(),
-    })?;
 
-    Ok((version, r))
+    /// returns the version and the underlying reader
+    pub fn into_parts(self) -> (Version, R) {
+        (self.version, self.inner)
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for VersionedReader<R> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut TaskContext<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_read(cx, buf)
+    }
+}
+
+/// Creates a new [`VersionedReader`] from a stream. Same as [`VersionedReader::new`]
+pub async fn reader<R: AsyncRead + Unpin>(r: R) -> Result<VersionedReader<R>> {
+    VersionedReader::new(r).await
 }
 
 /// Reads versioned file's contents.
@@ -90,10 +137,21 @@ pub async fn read_file<P: AsRef<Path>>(path: P) -> Result<(Version, Vec<u8>)> {
         .read(true)
         .open(path.as_ref())
         .await?;
-    let (version, reader): (Version, &mut tokio::fs::File) = reader(&mut file).await?;
+    let mut reader = reader(&mut file).await?;
     let mut buf = Vec::new();
     reader.read_to_end(&mut buf).await?;
-    Ok((version, buf))
+    Ok((reader.version().clone(), buf))
+}
+
+/// Reads only the version of a versioned file, the data is not loaded.
+pub async fn peek_version<P: AsRef<Path>>(path: P) -> Result<Version> {
+    let file = tokio::fs::OpenOptions::new()
+        .read(true)
+        .open(path.as_ref())
+        .await?;
+    let reader = reader(file).await?;
+    let (version, _) = reader.into_parts();
+    Ok(version)
 }
 
 /// Writes version to a writer implementing [`AsyncWrite`].
@@ -104,30 +162,184 @@ pub async fn writer<W: AsyncWrite + Unpin>(mut w: W, version: &Version) -> Resul
 }
 
 /// Writes version and data to a file.
+///
+/// The data is first written to a temporary file next to the target, which is then
+/// synced and renamed over the target. So the target is either fully updated or left
+/// untouched if the process crashes in the middle of the write.
 pub async fn write_file<P: AsRef<Path>>(
     path: P,
     version: &Version,
     data: &[u8],
     perm: Permissions,
 ) -> Result<()> {
+    let path = path.as_ref();
+    let tmp = temp_path(path)?;
+    let result = write_tmp(&tmp, version, data, perm).await;
+    let result = match result {
+        Ok(_) => tokio::fs::rename(&tmp, path).await.map_err(Error::from),
+        Err(err) => Err(err),
+    };
+
+    if result.is_err() {
+        let _ = tokio::fs::remove_file(&tmp).await;
+        return result;
+    }
+
+    // the rename is only durable once the directory entry is flushed
+    sync_parent(path).await
+}
+
+// sync_parent fsyncs the directory that contains the given path
+async fn sync_parent(path: &Path) -> Result<()> {
+    let parent = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+
+    let dir = tokio::fs::File::open(parent)
+        .await
+        .with_context(|| format!("failed to open directory {:?}", parent))?;
+    dir.sync_all()
+        .await
+        .with_context(|| format!("failed to sync directory {:?}", parent))?;
+
+    Ok(())
+}
+
+// temp_path returns a hidden sibling path of the given path
+fn temp_path(path: &Path) -> Result<PathBuf> {
+    let name = path
+        .file_name()
+        .ok_or_else(|| anyhow::anyhow!("invalid file path: {:?}", path))?;
+
+    let mut tmp = OsString::from(".");
+    tmp.push(name);
+    tmp.push(format!(".{}.tmp", uuid::Uuid::new_v4().simple()));
+
+    Ok(path.with_file_name(tmp))
+}
+
+async fn write_tmp(path: &Path, version: &Version, data: &[u8], perm: Permissions) -> Result<()> {
     let file = tokio::fs::OpenOptions::new()
         .mode(perm.mode())
         .truncate(true)
         .create(true)
         .write(true)
-        .open(path.as_ref())
+        .open(path)
         .await?;
     let mut file = writer(file, version).await?;
     file.write_all(data).await?;
+    file.sync_all().await?;
     Ok(())
 }
 
+/// Writes version and the json encoded object to a file.
+pub async fn write_object<P: AsRef<Path>, T: Serialize>(
+    path: P,
+    version: &Version,
+    object: &T,
+    perm: Permissions,
+) -> Result<()> {
+    let data = serde_json::to_vec(object)?;
+    write_file(path, version, &data, perm).await
+}
+
+/// Reads a versioned file and decodes its json content.
+///
+/// If read succeeds, returns a tuple `(semver::Version, T)` containing file version and the object inside [`Ok`].
+pub async fn read_object<P: AsRef<Path>, T: DeserializeOwned>(path: P) -> Result<(Version, T)> {
+    let (version, data) = read_file(path).await?;
+    let object = serde_json::from_slice(&data)?;
+    Ok((version, object))
+}
+
+/// A migration function converts data from one version to the next
+pub type MigrationFn = fn(&[u8]) -> Result<Vec<u8>>;
+
+struct Migration {
+    from: VersionReq,
+    to: Version,
+    migrate: MigrationFn,
+}
+
+/// Migrator holds an ordered list of migration steps used to upgrade
+/// versioned data to the latest known version.
+#[derive(Default)]
+pub struct Migrator {
+    steps: Vec<Migration>,
+}
+
+impl Migrator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// register a migration step that converts data with a version matching `from`
+    /// to version `to`. Steps are tried in the order they are registered.
+    pub fn step(mut self, from: VersionReq, to: Version, migrate: MigrationFn) -> Self {
+        self.steps.push(Migration { from, to, migrate });
+        self
+    }
+
+    /// latest version known by this migrator (if any steps are registered)
+    pub fn latest(&self) -> Option<&Version> {
+        self.steps.iter().map(|s| &s.to).max()
+    }
+
+    /// migrate applies matching steps in order until data is at the latest version.
+    /// If data is already at (or beyond) the latest version it's returned as is.
+    pub fn migrate(&self, mut version: Version, mut data: Vec<u8>) -> Result<(Version, Vec<u8>)> {
+        let latest = match self.latest() {
+            Some(latest) => latest,
+            None => return Ok((version, data)),
+        };
+
+        while &version < latest {
+            // only accept steps that move the version forward, this
+            // makes sure we never loop forever
+            let step = self
+                .steps
+                .iter()
+                .find(|s| s.from.matches(&version) && s.to > version)
+                .ok_or_else(|| Error::NoMigrationPath {
+                    version: version.clone(),
+                })?;
+
+            data = (step.migrate)(&data)?;
+            version = step.to.clone();
+        }
+
+        Ok((version, data))
+    }
+}
+
+/// Reads versioned file's contents and migrates it to the latest version of the migrator.
+/// If the data was migrated, the file is rewritten (with the same permissions) with the new
+/// version and data.
+pub async fn load_migrated<P: AsRef<Path>>(
+    path: P,
+    migrator: &Migrator,
+) -> Result<(Version, Vec<u8>)> {
+    let path = path.as_ref();
+    let (version, data) = read_file(path).await?;
+    let (migrated, data) = migrator.migrate(version.clone(), data)?;
+    if migrated != version {
+        let perm = tokio::fs::metadata(path).await?.permissions();
+        write_file(path, &migrated, &data, perm).await?;
+    }
+
+    Ok((migrated, data))
+}
+
 #[cfg(test)]
 
 mod test {
-    use super::{read_file, write_file, Error};
+    use super::{
+        load_migrated, peek_version, read_file, read_object, write_file, write_object, Error,
+        Migrator,
+    };
     use rand::{self, Rng};
-    use semver::Version;
+    use semver::{Version, VersionReq};
     use std::io::Write;
     use std::str::FromStr;
     use std::{fs::Permissions, os::unix::prelude::PermissionsExt};
@@ -158,6 +370,20 @@ mod test {
         assert!(matches!(versioned, Err(Error::InvalidVersion{version}) if version == "mario"));
     }
 
+    #[tokio::test]
+    async fn test_reader() {
+        use tokio::io::AsyncReadExt;
+
+        let mut reader = super::reader(r#""1.2.3"hello world"#.as_bytes())
+            .await
+            .unwrap();
+        assert_eq!(reader.version(), &Version::new(1, 2, 3));
+
+        let mut data = String::new();
+        reader.read_to_string(&mut data).await.unwrap();
+        assert_eq!(data, "hello world");
+    }
+
     #[tokio::test]
     async fn test_unversioned() {
         let versioned = super::reader(r#"1.2.3"abcdef"#.as_bytes()).await;
@@ -191,4 +417,132 @@ mod test {
         assert_eq!(version, read_version);
         assert_eq!(data, read_data);
     }
+
+    #[tokio::test]
+    async fn test_load_migrated() {
+        fn v1_to_v1_1(data: &[u8]) -> super::Result<Vec<u8>> {
+            let mut data = data.to_vec();
+            data.extend_from_slice(b" v1.1");
+            Ok(data)
+        }
+
+        fn v1_1_to_v2(data: &[u8]) -> super::Result<Vec<u8>> {
+            let mut data = data.to_vec();
+            data.extend_from_slice(b" v2");
+            Ok(data)
+        }
+
+        let migrator = Migrator::new()
+            .step(
+                VersionReq::parse("^1.0").unwrap(),
+                Version::new(1, 1, 0),
+                v1_to_v1_1,
+            )
+            .step(
+                VersionReq::parse("~1.1").unwrap(),
+                Version::new(2, 0, 0),
+                v1_1_to_v2,
+            );
+
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        write!(file, r#""1.0.0"data"#).unwrap();
+
+        let (version, data) = load_migrated(file.path(), &migrator).await.unwrap();
+        assert_eq!(version, Version::new(2, 0, 0));
+        assert_eq!(data, b"data v1.1 v2");
+
+        // file is updated in place
+        let (version, data) = read_file(file.path()).await.unwrap();
+        assert_eq!(version, Version::new(2, 0, 0));
+        assert_eq!(data, b"data v1.1 v2");
+
+        // loading again is a no-op
+        let (version, data) = load_migrated(file.path(), &migrator).await.unwrap();
+        assert_eq!(version, Version::new(2, 0, 0));
+        assert_eq!(data, b"data v1.1 v2");
+    }
+
+    #[tokio::test]
+    async fn test_load_migrated_no_path() {
+        fn noop(data: &[u8]) -> super::Result<Vec<u8>> {
+            Ok(data.to_vec())
+        }
+
+        let migrator = Migrator::new().step(
+            VersionReq::parse("~1.1").unwrap(),
+            Version::new(2, 0, 0),
+            noop,
+        );
+
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        write!(file, r#""1.0.0"data"#).unwrap();
+
+        let result = load_migrated(file.path(), &migrator).await;
+        assert!(
+            matches!(result, Err(Error::NoMigrationPath { version }) if version == Version::new(1, 0, 0))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_write_file_no_leftovers() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("seed.txt");
+        let version = Version::new(1, 0, 0);
+
+        write_file(&path, &version, b"first", Permissions::from_mode(0o600))
+            .await
+            .unwrap();
+        write_file(&path, &version, b"second", Permissions::from_mode(0o600))
+            .await
+            .unwrap();
+
+        let (read_version, read_data) = read_file(&path).await.unwrap();
+        assert_eq!(version, read_version);
+        assert_eq!(b"second".to_vec(), read_data);
+
+        let entries: Vec<_> = std::fs::read_dir(dir.path())
+            .unwrap()
+            .map(|e| e.unwrap().file_name())
+            .collect();
+        assert_eq!(entries, vec![std::ffi::OsString::from("seed.txt")]);
+    }
+
+    #[tokio::test]
+    async fn test_write_read_object() {
+        #[derive(Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+        struct Seed {
+            name: String,
+            id: u32,
+        }
+
+        let seed = Seed {
+            name: "node".into(),
+            id: 10,
+        };
+        let version = Version::new(1, 1, 0);
+        let file = tempfile::NamedTempFile::new().unwrap();
+        write_object(file.path(), &version, &seed, Permissions::from_mode(0o600))
+            .await
+            .unwrap();
+
+        let (read_version, read_seed): (Version, Seed) = read_object(file.path()).await.unwrap();
+        assert_eq!(version, read_version);
+        assert_eq!(seed, read_seed);
+
+        let result: super::Result<(Version, u32)> = read_object(file.path()).await;
+        assert!(matches!(result, Err(Error::Serde(_))));
+    }
+
+    #[tokio::test]
+    async fn test_peek_version() {
+        let data = vec![0xAA; 8 * crate::MEGABYTE as usize];
+        let version = Version::from_str("3.1.4-rc1").unwrap();
+        let file = tempfile::NamedTempFile::new().unwrap();
+        write_file(file.path(), &version, &data, Permissions::from_mode(0o600))
+            .await
+            .unwrap();
+
+        let peeked = peek_version(file.path()).await.unwrap();
+        assert_eq!(version, peeked);
+    }
 }